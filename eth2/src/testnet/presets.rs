@@ -0,0 +1,74 @@
+/// Known eth2 networks that share the discv5 DHT, keyed by fork digest.
+pub struct NetworkPreset {
+    pub name: &'static str,
+    pub fork_digest: &'static str,
+    /// Unix time of the genesis block, when known.
+    pub genesis_time: Option<u64>,
+}
+
+pub const NETWORK_PRESETS: &[NetworkPreset] = &[
+    NetworkPreset {
+        name: "mainnet",
+        fork_digest: "b5303f2a",
+        genesis_time: Some(1606824023),
+    },
+    NetworkPreset {
+        name: "witti",
+        fork_digest: "f6775d07",
+        genesis_time: None,
+    },
+    NetworkPreset {
+        name: "topaz",
+        fork_digest: "f071c66c",
+        genesis_time: None,
+    },
+    NetworkPreset {
+        name: "altona",
+        fork_digest: "fdca39b0",
+        genesis_time: Some(1593433805),
+    },
+    NetworkPreset {
+        name: "onyx",
+        fork_digest: "a65b4897",
+        genesis_time: None,
+    },
+    NetworkPreset {
+        name: "medalla",
+        fork_digest: "e7a75d5a",
+        genesis_time: Some(1596546008),
+    },
+    NetworkPreset {
+        name: "prysm-attack",
+        fork_digest: "c354a54a",
+        genesis_time: None,
+    },
+    NetworkPreset {
+        name: "lighthouse-attack",
+        fork_digest: "80e1769b",
+        genesis_time: None,
+    },
+    NetworkPreset {
+        name: "teku-attack",
+        fork_digest: "157d3034",
+        genesis_time: None,
+    },
+    NetworkPreset {
+        name: "mc-attack",
+        fork_digest: "2e44918e",
+        genesis_time: None,
+    },
+];
+
+pub fn get_preset_by_fork_digest(fork_digest: &str) -> Option<&'static NetworkPreset> {
+    NETWORK_PRESETS
+        .iter()
+        .find(|preset| preset.fork_digest == fork_digest)
+}
+
+pub fn get_network_name(fork_digest: &str) -> Option<&'static str> {
+    get_preset_by_fork_digest(fork_digest).map(|preset| preset.name)
+}
+
+pub fn get_genesis_time(fork_digest: &str) -> Option<u64> {
+    get_preset_by_fork_digest(fork_digest).and_then(|preset| preset.genesis_time)
+}
@@ -4,7 +4,7 @@ use crate::libp2p::NetworkConfig;
 use crate::ssz::types::BitVector;
 use crate::ssz::{Decode, Encode};
 use crate::testnet::config::Eth2TestnetConfig;
-use crate::types::{ChainSpec, EnrForkId, EthSpec, Hash256, MainnetEthSpec, Slot};
+use crate::types::{ChainSpec, EnrForkId, EthSpec, Hash256, MainnetEthSpec, Slot, SubnetId};
 #[cfg(not(feature = "local"))]
 use discv5::enr::{CombinedKey, CombinedPublicKey, Enr};
 #[cfg(feature = "local")]
@@ -13,8 +13,9 @@ use libp2p_core::{identity::Keypair, identity::PublicKey, multiaddr::Protocol, M
 
 use std::path::PathBuf;
 
-pub fn load_testnet_config<E: EthSpec>(testnet_dir: PathBuf) -> Eth2TestnetConfig<E> {
-    Eth2TestnetConfig::load(testnet_dir).unwrap()
+pub fn load_testnet_config<E: EthSpec>(testnet_dir: PathBuf) -> Result<Eth2TestnetConfig<E>, String> {
+    Eth2TestnetConfig::load(testnet_dir.clone())
+        .map_err(|e| format!("Failed to load testnet dir {:?}: {}", testnet_dir, e))
 }
 
 pub fn get_eth2_config() -> Eth2Config {
@@ -55,14 +56,26 @@ pub fn get_fork_id(
     }
 }
 
-pub fn get_fork_id_from_dir(dir: Option<PathBuf>) -> Option<EnrForkId> {
+pub fn get_fork_id_from_dir(dir: Option<PathBuf>) -> Result<Option<EnrForkId>, String> {
     if let Some(value) = dir {
-        let config = load_testnet_config::<MainnetEthSpec>(value);
-        let state = config.genesis_state.unwrap();
+        let config = load_testnet_config::<MainnetEthSpec>(value.clone())?;
+        let state = config
+            .genesis_state
+            .ok_or_else(|| format!("Testnet dir {:?} has no genesis state", value))?;
         let spec = get_chain_spec();
-        Some(spec.enr_fork_id(state.slot, state.genesis_validators_root))
+        Ok(Some(spec.enr_fork_id(state.slot, state.genesis_validators_root)))
     } else {
-        None
+        Ok(None)
+    }
+}
+
+pub fn get_genesis_time_from_dir(dir: Option<PathBuf>) -> Result<Option<u64>, String> {
+    match dir {
+        Some(dir) => {
+            let config = load_testnet_config::<MainnetEthSpec>(dir)?;
+            Ok(config.genesis_state.map(|state| state.genesis_time))
+        }
+        None => Ok(None),
     }
 }
 
@@ -129,6 +142,16 @@ pub fn create_topic_ids(enr_fork_id: EnrForkId) -> Vec<String> {
     topic_ids
 }
 
+/// Topic ids of the `beacon_attestation_{subnet_id}` topics of `subnets`.
+pub fn create_attestation_topic_ids(enr_fork_id: EnrForkId, subnets: &[u64]) -> Vec<String> {
+    subnets
+        .iter()
+        .map(|subnet| {
+            get_gossip_topic_id(GossipKind::Attestation(SubnetId::new(*subnet)), enr_fork_id.clone())
+        })
+        .collect()
+}
+
 pub fn get_gossip_topic_id(kind: GossipKind, enr_fork_id: EnrForkId) -> String {
     GossipTopic::new(kind, GossipEncoding::default(), enr_fork_id.fork_digest).into()
 }
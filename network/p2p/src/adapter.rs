@@ -1,162 +1,796 @@
+use crate::block_tree::{BlockEvent, BlockTree};
+use crate::blocks::BlockStore;
+use crate::chain::ChainState;
+use crate::columnar::{ParquetRecord, ParquetSink};
+use crate::feed::{Feed, BLOCK_EVENT, GOSSIP_EVENT};
+use crate::gossip::{self, GossipMessage, GossipRecord, RecentRecords};
+use crate::message_id::verify_message_id;
+use crate::metadata::{parse_attnets, LocalMetaData};
+use crate::metrics;
+use crate::output::{OutputFormat, Sink};
+use crate::payload_archive::{Arrival, PayloadArchive};
+use crate::peer_events::PeerEvent;
+use crate::participation::SharedParticipation;
+use crate::peers::{PeerSnapshots, SharedPeerTable};
+use crate::propagation::SharedPropagation;
+use crate::rotation::Rotation;
+use crate::slots::{EpochRecord, SharedSlotStats, SlotRecord, SlotStats};
+use crate::writer::{self, RecordSink, RecordWriter, WriterHandle};
 use chrono::Local;
 use clap::ArgMatches;
 use csv;
 use eth2::ssz::{Decode, Encode};
-use eth2::types::{MainnetEthSpec, SignedBeaconBlock, SignedAggregateAndProof, Hash256, Slot, Epoch, EnrForkId};
+use eth2::types::{Attestation, EnrForkId, Hash256, MainnetEthSpec, SignedBeaconBlock, Slot};
 use eth2::libp2p::{rpc, PeerId};
-use eth2::utils::{create_topic_ids, get_fork_id_from_dir, get_fork_id_from_string};
-use serde_derive::Serialize;
+use eth2::testnet::presets::get_genesis_time;
+use eth2::utils::{
+    create_attestation_topic_ids, create_topic_ids, get_chain_spec, get_fork_id_from_dir, get_fork_id_from_string,
+    get_genesis_time_from_dir,
+};
+use serde::Serialize;
 use slog::{debug, info, o, trace, warn};
-use snap::raw::{decompress_len, Decoder, Encoder};
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use dirs;
-use std::time::SystemTime;
+use futures::StreamExt;
+use std::time::{Duration, SystemTime};
 use tokio::sync::watch;
 use tokio::{runtime, signal, sync::mpsc, task, time};
 use types::events::Events;
 
 #[cfg(not(feature = "local"))]
-use mothra::{Mothra, NetworkGlobals, NetworkMessage, Subscriber, TaskExecutor, rpc::RequestId,  Request, Response, MothraPeerId};
+use mothra::{Mothra, NetworkGlobals, NetworkMessage, Subscriber, TaskExecutor, rpc::{RequestId, RPCResponseErrorCode},  Request, Response, MothraPeerId};
 #[cfg(feature = "local")]
-use mothra_local::{Mothra, NetworkGlobals, NetworkMessage, Subscriber, TaskExecutor, rpc::RequestId, Request, Response, MothraPeerId};
-
-#[derive(Serialize, Default, Clone)]
-struct GossipRecord {
-    index: u64,
-    timestamp: String,
-    message_id: String,
-    sequence_number: u64,
-    agent_string: String,
-    peer_id: String,
-    topic: String,
-    message_size: usize,
-    slot: u64,
-    validator_index: u64,
+use mothra_local::{Mothra, NetworkGlobals, NetworkMessage, Subscriber, TaskExecutor, rpc::{RequestId, RPCResponseErrorCode}, Request, Response, MothraPeerId};
+
+enum FileSink<R: ParquetRecord> {
+    Serde(Sink),
+    Parquet(ParquetSink<R>),
 }
-//SignedBeaconBlock
-impl GossipRecord {
-    pub fn new(
-        index: u64,
-        timestamp: String,
+
+impl<R> RecordSink<R> for FileSink<R>
+where
+    R: ParquetRecord + Serialize + Send + 'static,
+{
+    fn write(&mut self, record: R) -> io::Result<()> {
+        match self {
+            FileSink::Serde(sink) => sink.write(&record),
+            // parquet records are buffered into row groups
+            FileSink::Parquet(sink) => sink.write(record),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileSink::Serde(sink) => sink.flush(),
+            FileSink::Parquet(sink) => sink.flush(),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self {
+            FileSink::Serde(sink) => sink.flush(),
+            FileSink::Parquet(sink) => sink.close(),
+        }
+    }
+}
+
+/// Opens `<output_dir>/<name>.<format>` on a writer thread, archiving the
+/// output of the previous run instead of truncating it.
+fn spawn_file_writer<R>(
+    name: &'static str,
+    output_dir: &Path,
+    output_format: OutputFormat,
+    rotation: Rotation,
+    log: &slog::Logger,
+) -> (Option<RecordWriter<R>>, Option<WriterHandle<R>>)
+where
+    R: ParquetRecord + Serialize + Send + 'static,
+{
+    let previous = output_dir.join(format!("{}.{}", name, output_format.extension()));
+    if let Err(e) = rotation.archive(&previous) {
+        warn!(log, "Failed to archive {:?}: {}", previous, e);
+    }
+    let sink = match output_format {
+        OutputFormat::Parquet => ParquetSink::create(output_dir, name, rotation).map(FileSink::Parquet),
+        _ => Sink::create(output_format, output_dir, name, true)
+            .map(|sink| FileSink::Serde(sink.with_rotation(rotation))),
+    };
+    match sink {
+        Ok(sink) => {
+            let (writer, writer_handle) = writer::spawn(name, sink, log.clone());
+            (Some(writer), Some(writer_handle))
+        }
+        Err(e) => {
+            warn!(log, "Failed to open {} output: {}", name, e);
+            (None, None)
+        }
+    }
+}
+
+/// Opens the event log `<output_dir>/<name>.jsonl` on a writer thread.
+/// Events of every run are appended to the same log until it rolls over.
+fn spawn_event_writer<R>(
+    name: &'static str,
+    output_dir: &Path,
+    rotation: Rotation,
+    log: &slog::Logger,
+) -> (Option<RecordWriter<R>>, Option<WriterHandle<R>>)
+where
+    R: Serialize + Send + 'static,
+{
+    match Sink::create(OutputFormat::Jsonl, output_dir, name, false) {
+        Ok(sink) => {
+            let (writer, writer_handle) = writer::spawn(name, sink.with_rotation(rotation), log.clone());
+            (Some(writer), Some(writer_handle))
+        }
+        Err(e) => {
+            warn!(log, "Failed to open the {} log: {}", name, e);
+            (None, None)
+        }
+    }
+}
+
+/// Owners of the writer threads of a `Client`.
+pub(crate) struct ClientWriters {
+    gossip: Option<WriterHandle<GossipRecord>>,
+    slots: Option<WriterHandle<SlotRecord>>,
+    epochs: Option<WriterHandle<EpochRecord>>,
+    block_events: Option<WriterHandle<BlockEvent>>,
+    payload_archive: Option<WriterHandle<Arrival>>,
+}
+
+impl ClientWriters {
+    /// Writes and flushes every queued record and stops the threads.
+    pub(crate) fn close(self) {
+        if let Some(gossip) = self.gossip {
+            gossip.close();
+        }
+        if let Some(slots) = self.slots {
+            slots.close();
+        }
+        if let Some(epochs) = self.epochs {
+            epochs.close();
+        }
+        if let Some(block_events) = self.block_events {
+            block_events.close();
+        }
+        if let Some(payload_archive) = self.payload_archive {
+            payload_archive.close();
+        }
+    }
+}
+
+pub(crate) struct Client {
+    writer: Option<RecordWriter<GossipRecord>>,
+    slot_writer: Option<RecordWriter<SlotRecord>>,
+    epoch_writer: Option<RecordWriter<EpochRecord>>,
+    num_records: Cell<u64>,
+    feed: Option<Feed>,
+    network_send: Option<mpsc::UnboundedSender<NetworkMessage>>,
+    fork_digest: Option<[u8; 4]>,
+    genesis_time: Option<u64>,
+    milliseconds_per_slot: u64,
+    propagation: SharedPropagation,
+    recent_records: RefCell<RecentRecords>,
+    payload_archive: Option<RecordWriter<Arrival>>,
+    chain: RefCell<ChainState>,
+    blocks: RefCell<BlockStore>,
+    block_tree: RefCell<BlockTree>,
+    block_events: Option<RecordWriter<BlockEvent>>,
+    peers: SharedPeerTable,
+    peer_events: Option<RecordWriter<PeerEvent>>,
+    slot_stats: SharedSlotStats,
+    participation: SharedParticipation,
+    log: slog::Logger,
+}
+
+/// Values of the `req_resp` argument of `Subscriber::receive_rpc`.
+/// A new peer connected and expects us to open with a Status request.
+const RPC_CONNECTED: u8 = 0;
+/// An inbound request. The first data byte is the index of the substream
+/// the response has to be sent on, followed by the ssz request.
+const RPC_REQUEST: u8 = 1;
+// anything else is a response to one of our requests, carrying the ssz
+// response as data
+
+/// Blocks kept to answer BlocksByRange and BlocksByRoot, a little over a
+/// day of slots.
+const BLOCK_STORE_CAPACITY: usize = 8192;
+
+/// The req/resp methods imp understands, matched on the protocol name mothra
+/// passes to `receive_rpc`.
+#[derive(Clone, Copy, PartialEq)]
+enum RpcMethod {
+    Status,
+    BlocksByRange,
+    BlocksByRoot,
+    MetaData,
+    Goodbye,
+    Other,
+}
+
+impl RpcMethod {
+    fn from_name(method: &str) -> Self {
+        let method = method.to_lowercase();
+        if method.contains("status") {
+            RpcMethod::Status
+        } else if method.contains("blocks_by_range") {
+            RpcMethod::BlocksByRange
+        } else if method.contains("blocks_by_root") {
+            RpcMethod::BlocksByRoot
+        } else if method.contains("metadata") {
+            RpcMethod::MetaData
+        } else if method.contains("goodbye") {
+            RpcMethod::Goodbye
+        } else {
+            RpcMethod::Other
+        }
+    }
+}
+
+impl Client {
+    pub(crate) fn new(
+        output_dir: &Path,
+        output_format: OutputFormat,
+        rotation: Rotation,
+        feed: Option<Feed>,
+        genesis_time: Option<u64>,
+        propagation: SharedPropagation,
+        payload_archive: Option<PayloadArchive>,
+        peers: SharedPeerTable,
+        peer_events: Option<RecordWriter<PeerEvent>>,
+        slot_stats: SharedSlotStats,
+        participation: SharedParticipation,
+        log: slog::Logger,
+    ) -> (Self, ClientWriters) {
+        let (block_events, block_events_handle) =
+            spawn_event_writer("block_events", output_dir, rotation, &log);
+        let (writer, gossip) = spawn_file_writer("gossip", output_dir, output_format, rotation, &log);
+        // slots and epochs are a time series, streaming formats only carry gossip
+        let (slot_writer, slots, epoch_writer, epochs) = if output_format.is_streaming() {
+            (None, None, None, None)
+        } else {
+            let (slot_writer, slots) = spawn_file_writer("slots", output_dir, output_format, rotation, &log);
+            let (epoch_writer, epochs) = spawn_file_writer("epochs", output_dir, output_format, rotation, &log);
+            (slot_writer, slots, epoch_writer, epochs)
+        };
+        let (payload_archive, payload_archive_handle) = match payload_archive {
+            Some(payload_archive) => {
+                let (writer, writer_handle) = writer::spawn("payload_archive", payload_archive, log.clone());
+                (Some(writer), Some(writer_handle))
+            }
+            None => (None, None),
+        };
+        let client = Client {
+            writer,
+            slot_writer,
+            epoch_writer,
+            num_records: Cell::new(0),
+            feed,
+            network_send: None,
+            fork_digest: None,
+            genesis_time,
+            milliseconds_per_slot: get_chain_spec().milliseconds_per_slot,
+            propagation,
+            recent_records: RefCell::new(RecentRecords::default()),
+            payload_archive,
+            chain: RefCell::new(ChainState::new([0; 4])),
+            blocks: RefCell::new(BlockStore::new(BLOCK_STORE_CAPACITY)),
+            block_tree: RefCell::new(BlockTree::default()),
+            block_events,
+            peers,
+            peer_events,
+            slot_stats,
+            participation,
+            log,
+        };
+        let writers = ClientWriters {
+            gossip,
+            slots,
+            epochs,
+            block_events: block_events_handle,
+            payload_archive: payload_archive_handle,
+        };
+        (client, writers)
+    }
+
+    fn write_record(&self, record: GossipRecord) {
+        if let Some(writer) = &self.writer {
+            writer.write(record);
+        }
+    }
+
+    /// Decodes and records a gossip message that arrived at `arrival`, the
+    /// time since the unix epoch. Live and replayed messages both end up here.
+    pub(crate) fn handle_gossip(
+        &self,
+        arrival: Duration,
         message_id: String,
         sequence_number: u64,
         agent_string: String,
         peer_id: String,
         topic: String,
         data: Vec<u8>,
-    ) -> Result<Self, String> {
-        let mut decoder = Decoder::new();
-        let mut decompressed_data: Vec<u8> = Vec::new();
-        match decompress_len(&data) {
-            Ok(n) if n > 1_048_576 => {
-                return Err("ssz_snappy decoded data > GOSSIP_MAX_SIZE".into());
+    ) {
+        metrics::GOSSIP_MESSAGES.with_label_values(&[metrics::topic_kind(&topic)]).inc();
+        self.peers
+            .write()
+            .unwrap()
+            .on_gossip(&peer_id, &agent_string, &topic, arrival.as_millis() as u64);
+        if gossip::is_supported_topic(&topic) {
+            let arrival_ms = arrival.as_millis() as u64;
+            let timestamp = format!(
+                "{}.{}",
+                arrival.as_secs(),
+                pad_millis(arrival.subsec_millis()).expect("unexpected subsec_millis value")
+            );
+            if let Some(payload_archive) = &self.payload_archive {
+                payload_archive.write(Arrival {
+                    arrival_ms,
+                    message_id: message_id.clone(),
+                    sequence_number,
+                    agent_string: agent_string.clone(),
+                    peer_id: peer_id.clone(),
+                    topic: topic.clone(),
+                    data: data.clone(),
+                });
+            }
+            let seen = self.propagation.write().unwrap().observe(
+                &message_id,
+                &topic,
+                &peer_id,
+                &agent_string,
+                arrival_ms,
+            );
+            if seen.deliveries == 1 {
+                metrics::GOSSIP_FIRST_DELIVERIES
+                    .with_label_values(&[metrics::agent_client(&agent_string)])
+                    .inc();
+            } else {
+                metrics::GOSSIP_DUPLICATES
+                    .with_label_values(&[metrics::topic_kind(&topic)])
+                    .inc();
+            }
+            // duplicates reuse the record of an earlier delivery instead of
+            // being decompressed, verified and decoded again
+            let cached = match seen.deliveries {
+                1 => None,
+                _ => self.recent_records.borrow().get(&message_id).cloned(),
+            };
+            let mut record = match cached {
+                Some(mut record) => {
+                    record.index = self.num_records.get();
+                    record.timestamp = timestamp.clone();
+                    record.sequence_number = sequence_number;
+                    record.agent_string = agent_string.clone();
+                    record.peer_id = peer_id.clone();
+                    record
+                }
+                None => match self.decode_gossip(
+                    arrival_ms,
+                    timestamp.clone(),
+                    message_id.clone(),
+                    sequence_number,
+                    agent_string.clone(),
+                    peer_id.clone(),
+                    topic.clone(),
+                    &data,
+                ) {
+                    Some(record) => record,
+                    None => return,
+                },
+            };
+            let offset = self.genesis_time.and_then(|genesis_time| {
+                gossip::slot_offset_ms(genesis_time, self.milliseconds_per_slot, record.slot, arrival_ms)
+            });
+            record.slot_offset_ms = offset;
+            if let Some(offset) = offset {
+                metrics::GOSSIP_ARRIVAL_DELAY
+                    .with_label_values(&[
+                        metrics::topic_kind(&topic),
+                        metrics::agent_client(&agent_string),
+                    ])
+                    .observe(offset as f64 / 1000.0);
+            }
+            record.spread_ms = seen.spread_ms();
+            record.deliveries = seen.deliveries;
+            record.first_peer_id = seen.first_peer_id;
+            if let Some(feed) = &self.feed {
+                feed.publish(GOSSIP_EVENT, &record);
+            }
+            self.write_record(record);
+            self.num_records.set(self.num_records.get() + 1);
+            trace!(
+                self.log,
+                "Received gossip";
+                "message_id" => &message_id,
+                "timestamp" => &timestamp,
+                "peer_id" => &peer_id,
+                "agent" => &agent_string
+            );
+        }
+    }
+
+    /// Decodes a message id that has no cached record yet and feeds blocks
+    /// and attestations to the chain and participation views. Returns `None`
+    /// if the payload cannot be decoded.
+    fn decode_gossip(
+        &self,
+        arrival_ms: u64,
+        timestamp: String,
+        message_id: String,
+        sequence_number: u64,
+        agent_string: String,
+        peer_id: String,
+        topic: String,
+        data: &[u8],
+    ) -> Option<GossipRecord> {
+        let decompressed = gossip::decompress(data);
+        let message_id_valid =
+            verify_message_id(&message_id, data, decompressed.as_ref().ok().map(|x| x.as_slice()))
+                .is_some();
+        if !message_id_valid {
+            metrics::GOSSIP_MESSAGE_ID_MISMATCHES
+                .with_label_values(&[metrics::agent_client(&agent_string)])
+                .inc();
+            debug!(
+                self.log,
+                "Message-id mismatch";
+                "message_id" => &message_id,
+                "peer_id" => &peer_id,
+                "agent" => &agent_string
+            );
+        }
+        match decompressed.and_then(|data| {
+            GossipRecord::new(
+                self.num_records.get(),
+                timestamp,
+                message_id.clone(),
+                sequence_number,
+                agent_string,
+                peer_id,
+                topic.clone(),
+                &data,
+            )
+        }) {
+            Ok((mut record, message)) => {
+                record.message_id_valid = message_id_valid;
+                match message {
+                    GossipMessage::Block(block) => self.on_block(*block, arrival_ms),
+                    GossipMessage::Aggregate(aggregate) => {
+                        self.on_attestation(&aggregate.message.aggregate)
+                    }
+                    GossipMessage::Attestation(_, attestation) => {
+                        self.on_attestation(&attestation)
+                    }
+                    _ => {}
+                }
+                self.recent_records.borrow_mut().insert(record.clone());
+                Some(record)
             }
-            Ok(n) => decompressed_data.resize(n, 0),
             Err(e) => {
-                return Err(format!("{}", e));
+                metrics::GOSSIP_DECODE_ERRORS.with_label_values(&[metrics::topic_kind(&topic)]).inc();
+                debug!(self.log, "Failed to decode gossip"; "message_id" => &message_id, "topic" => &topic, "error" => e.to_string());
+                None
             }
+        }
+    }
+}
+
+impl Client {
+    /// Returns the wall-clock slot, if the genesis time is known.
+    fn current_slot(&self) -> Option<Slot> {
+        let genesis_time = self.genesis_time?;
+        let since_genesis = now_millis().checked_sub(genesis_time.checked_mul(1000)?)?;
+        Some(Slot::new(since_genesis / self.milliseconds_per_slot))
+    }
+
+    /// Returns false for slots more than one slot ahead of the wall clock,
+    /// which no honest peer sends. Without a genesis time every slot passes.
+    fn is_timely(&self, slot: Slot) -> bool {
+        self.current_slot()
+            .map_or(true, |current_slot| slot <= current_slot + 1)
+    }
+
+    /// Moves the head, tracks reorgs and keeps `block` to serve it to
+    /// syncing peers. `arrival_ms` is the unix time the block arrived at.
+    /// Blocks from future slots are dropped before they reach any of these.
+    fn on_block(&self, block: SignedBeaconBlock<MainnetEthSpec>, arrival_ms: u64) {
+        if !self.is_timely(block.message.slot) {
+            debug!(self.log, "Ignoring block from a future slot"; "slot" => block.message.slot.as_u64());
+            return;
+        }
+        let root = block.canonical_root();
+        let slot = block.message.slot;
+        let parent_root = block.message.parent_root;
+        let proposer_index = block.message.proposer_index;
+        // the same block can arrive over gossip and over BlocksByRange/Root,
+        // only its first arrival counts
+        let is_new = {
+            let mut blocks = self.blocks.borrow_mut();
+            let is_new = blocks.insert(root, block);
+            metrics::BLOCK_STORE_BLOCKS.set(blocks.len() as i64);
+            is_new
         };
-        let mut decoder = Decoder::new();
-        let data = match decoder.decompress(&data, &mut decompressed_data) {
-            Ok(n) => {
-                decompressed_data.truncate(n);
-                &decompressed_data
+        if !is_new {
+            return;
+        }
+        self.chain.borrow_mut().on_block(root, slot);
+        let events = self
+            .block_tree
+            .borrow_mut()
+            .insert(root, parent_root, slot, proposer_index, arrival_ms);
+        for event in events {
+            self.on_block_event(&event);
+        }
+        let arrival_offset_ms = self.genesis_time.and_then(|genesis_time| {
+            gossip::slot_offset_ms(genesis_time, self.milliseconds_per_slot, slot.as_u64(), arrival_ms)
+        });
+        let (slots, epochs) = self.slot_stats.write().unwrap().on_block(
+            slot.as_u64(),
+            root,
+            proposer_index,
+            arrival_offset_ms,
+        );
+        if let Some(slot_writer) = &self.slot_writer {
+            slots.into_iter().for_each(|record| slot_writer.write(record));
+        }
+        if let Some(epoch_writer) = &self.epoch_writer {
+            epochs.into_iter().for_each(|record| epoch_writer.write(record));
+        }
+    }
+
+    fn on_attestation(&self, attestation: &Attestation<MainnetEthSpec>) {
+        if !self.is_timely(attestation.data.slot) {
+            debug!(self.log, "Ignoring attestation from a future slot"; "slot" => attestation.data.slot.as_u64());
+            return;
+        }
+        let bits: Vec<bool> = attestation.aggregation_bits.iter().collect();
+        self.participation.write().unwrap().on_attestation(
+            attestation.data.slot.as_u64(),
+            attestation.data.index,
+            attestation.data.target.epoch.as_u64(),
+            attestation.data.target.root,
+            &bits,
+        );
+    }
+
+    /// Builds the response chunks to an inbound request, or `None` if imp
+    /// does not serve `method`. Block responses end with an empty chunk,
+    /// which mothra sends as the end of the stream. Requests imp cannot
+    /// decode or that ask for nothing are answered with `InvalidRequest` and
+    /// the returned reason.
+    fn respond(
+        &self,
+        method: RpcMethod,
+        peer: &str,
+        request: &[u8],
+    ) -> Option<Result<Vec<Response>, String>> {
+        let response = match method {
+            RpcMethod::Status => {
+                self.on_peer_status(peer, request);
+                Ok(vec![Response::Status(self.chain.borrow().status().as_ssz_bytes())])
             }
-            Err(e) => return Err(format!("{}", e)),
-        };
-        if topic.contains("beacon_block") {
-            match SignedBeaconBlock::<MainnetEthSpec>::from_ssz_bytes(&data) {
-                Ok(decoded_data) => Ok(GossipRecord {
-                    index,
-                    timestamp,
-                    message_id,
-                    sequence_number,
-                    agent_string,
-                    peer_id,
-                    topic,
-                    message_size: data.len(),
-                    slot: decoded_data.message.slot.into(),
-                    validator_index: decoded_data.message.proposer_index,
+            RpcMethod::BlocksByRange => rpc::methods::BlocksByRangeRequest::from_ssz_bytes(request)
+                .map_err(|e| format!("invalid BlocksByRange request: {:?}", e))
+                .and_then(|request| match request.step {
+                    0 => Err("step must be at least 1".to_string()),
+                    _ => Ok(request),
+                })
+                .map(|request| {
+                    // walk from the head of the blocks we received, a peer's
+                    // Status may name a head that never reached the store
+                    let head_root = self
+                        .block_tree
+                        .borrow()
+                        .head()
+                        .map_or_else(Hash256::zero, |(root, _)| root);
+                    let blocks = self.blocks.borrow();
+                    let mut response: Vec<Response> = blocks
+                        .blocks_by_range(request.start_slot, request.count, request.step, head_root)
+                        .into_iter()
+                        .map(|block| Response::BlocksByRange(Some(block.as_ssz_bytes())))
+                        .collect();
+                    metrics::RPC_BLOCKS_SERVED
+                        .with_label_values(&["blocks_by_range"])
+                        .inc_by(response.len() as i64);
+                    response.push(Response::BlocksByRange(None));
+                    response
                 }),
-                Err(e) => return Err(format!("{:#?}", e)),
-            }
-        } else {
-            match SignedAggregateAndProof::<MainnetEthSpec>::from_ssz_bytes(&data) {
-                Ok(decoded_data) => Ok(GossipRecord {
-                    index,
-                    timestamp,
-                    message_id,
-                    sequence_number,
-                    agent_string,
-                    peer_id,
-                    topic,
-                    message_size: data.len(),
-                    slot: decoded_data.message.aggregate.data.slot.into(),
-                    validator_index: decoded_data.message.aggregator_index,
+            RpcMethod::BlocksByRoot => rpc::methods::BlocksByRootRequest::from_ssz_bytes(request)
+                .map_err(|e| format!("invalid BlocksByRoot request: {:?}", e))
+                .map(|request| {
+                    let blocks = self.blocks.borrow();
+                    let mut response: Vec<Response> = blocks
+                        .blocks_by_root(&request.block_roots)
+                        .into_iter()
+                        .map(|block| Response::BlocksByRoot(Some(block.as_ssz_bytes())))
+                        .collect();
+                    metrics::RPC_BLOCKS_SERVED
+                        .with_label_values(&["blocks_by_root"])
+                        .inc_by(response.len() as i64);
+                    response.push(Response::BlocksByRoot(None));
+                    response
                 }),
-                Err(e) => return Err(format!("{:#?}", e)),
+            RpcMethod::MetaData | RpcMethod::Goodbye | RpcMethod::Other => return None,
+        };
+        if let Err(reason) = &response {
+            debug!(self.log, "Rejecting request"; "peer" => peer, "reason" => reason);
+        }
+        Some(response)
+    }
+
+    fn on_peer_status(&self, peer: &str, data: &[u8]) {
+        match rpc::methods::StatusMessage::from_ssz_bytes(data) {
+            Ok(status) => {
+                self.peers.write().unwrap().on_status(peer, &status, now_millis());
+                self.chain.borrow_mut().on_status(&status, self.current_slot());
+            }
+            Err(e) => debug!(self.log, "Invalid Status"; "peer" => peer, "error" => format!("{:?}", e)),
+        }
+    }
+
+    fn on_peer_goodbye(&self, peer: &str, data: &[u8]) {
+        let reason_code = match u64::from_ssz_bytes(data) {
+            Ok(reason_code) => reason_code,
+            Err(e) => {
+                debug!(self.log, "Invalid Goodbye"; "peer" => peer, "error" => format!("{:?}", e));
+                return;
+            }
+        };
+        let event = self.peers.write().unwrap().on_goodbye(peer, reason_code, now_millis());
+        let agent = metrics::agent_client(&event.agent_string);
+        metrics::PEER_GOODBYES
+            .with_label_values(&[agent, event.reason.unwrap_or("unknown")])
+            .inc();
+        if let Some(duration_ms) = event.duration_ms {
+            metrics::PEER_CONNECTION_SECONDS
+                .with_label_values(&[agent])
+                .observe(duration_ms as f64 / 1000.0);
+        }
+        self.write_peer_event(event);
+    }
+
+    fn on_block_event(&self, event: &BlockEvent) {
+        match event.depth {
+            Some(depth) => {
+                metrics::REORGS.inc();
+                metrics::REORG_DEPTH.observe(depth as f64);
+                warn!(
+                    self.log,
+                    "Reorg";
+                    "slot" => event.slot,
+                    "head" => &event.root,
+                    "old_head" => event.old_head_root.as_deref().unwrap_or("?"),
+                    "depth" => depth
+                );
             }
+            None => metrics::ORPHANED_BLOCKS.inc(),
+        }
+        if let Some(feed) = &self.feed {
+            feed.publish(BLOCK_EVENT, event);
+        }
+        if let Some(block_events) = &self.block_events {
+            block_events.write(event.clone());
+        }
+    }
+
+    fn write_peer_event(&self, event: PeerEvent) {
+        if let Some(peer_events) = &self.peer_events {
+            peer_events.write(event);
+        }
+    }
+
+    fn on_peer_meta_data(&self, peer: &str, data: &[u8]) {
+        match rpc::methods::MetaData::<MainnetEthSpec>::from_ssz_bytes(data) {
+            Ok(meta_data) => self.peers.write().unwrap().on_meta_data(peer, &meta_data, now_millis()),
+            Err(e) => debug!(self.log, "Invalid MetaData"; "peer" => peer, "error" => format!("{:?}", e)),
+        }
+    }
+
+    fn send(&self, message: NetworkMessage) {
+        if let Some(network_send) = &self.network_send {
+            network_send
+                .send(message)
+                .unwrap_or_else(|_| warn!(self.log, "Could not send RPC message to the network service"));
         }
     }
 }
 
-struct Client {
-    output_dir: String,
-    num_records: Cell<u64>,
-    network_send: Option<mpsc::UnboundedSender<NetworkMessage>>,
-    fork_digest: Option<[u8; 4]>
+/// Time between two polls of mothra's peer connection states.
+const CONNECTION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq)]
+enum ConnectionState {
+    Dialing,
+    Connected,
 }
 
-impl Client {
-    pub fn new() -> Self {
-        let output_dir = match dirs::home_dir() {
-            Some(path) => path.to_str().unwrap_or_default().to_string() + "/.imp",
-            None => "./.imp".to_string(),
-        };
-        Client {
-            output_dir: output_dir.to_string(),
-            num_records: Cell::new(0),
-            network_send: None,
-            fork_digest: None
-        }
-    }
-
-    fn write_file(&self, record: GossipRecord) {
-        let mut wtr = match record.index {
-            0 => {
-                let file = OpenOptions::new()
-                    .truncate(true)
-                    .write(true)
-                    .create(true)
-                    .append(false)
-                    .open(self.output_dir.clone() + "/gossip.csv")
-                    .unwrap();
-                csv::WriterBuilder::new()
-                    .has_headers(true)
-                    .from_writer(file)
+/// Records disconnects and failed dials, which the `Subscriber` callbacks do
+/// not report, by polling the connection state of mothra's peers.
+pub struct ConnectionWatch {
+    network_globals: Arc<NetworkGlobals>,
+    peers: SharedPeerTable,
+    peer_events: Option<RecordWriter<PeerEvent>>,
+}
+
+impl ConnectionWatch {
+    pub async fn run(self, mut shutdown_rx: watch::Receiver<Events>, log: slog::Logger) {
+        let mut interval = time::interval(CONNECTION_POLL_INTERVAL);
+        let mut states = HashMap::new();
+        loop {
+            tokio::select! {
+                x = shutdown_rx.recv() => {
+                    if let Some(Events::ShutdownMessage) = x {
+                        break;
+                    }
+                },
+                _ = interval.next() => states = self.poll(states),
             }
-            _ => {
-                let file = OpenOptions::new()
-                    .truncate(false)
-                    .write(true)
-                    .create(true)
-                    .append(true)
-                    .open(self.output_dir.clone() + "/gossip.csv")
-                    .unwrap();
-                csv::WriterBuilder::new()
-                    .has_headers(false)
-                    .from_writer(file)
+        }
+        debug!(log, "Connection watch stopped");
+    }
+
+    /// Compares the current connection states with the previous ones and
+    /// records the peers that left them.
+    fn poll(&self, previous: HashMap<String, ConnectionState>) -> HashMap<String, ConnectionState> {
+        let current: HashMap<String, ConnectionState> = self
+            .network_globals
+            .peers
+            .read()
+            .peers()
+            .filter_map(|(peer_id, info)| {
+                let state = if info.connection_status.is_connected() {
+                    ConnectionState::Connected
+                } else if info.connection_status.is_dialing() {
+                    ConnectionState::Dialing
+                } else {
+                    return None;
+                };
+                Some((peer_id.to_base58(), state))
+            })
+            .collect();
+        let now_ms = now_millis();
+        let mut peers = self.peers.write().unwrap();
+        for (peer_id, state) in previous {
+            let event = match (state, current.get(&peer_id)) {
+                (ConnectionState::Connected, Some(ConnectionState::Connected)) => None,
+                (ConnectionState::Connected, _) => peers.on_disconnected(&peer_id, now_ms),
+                (ConnectionState::Dialing, None) => Some(peers.on_dial_failed(&peer_id, now_ms)),
+                (ConnectionState::Dialing, Some(_)) => None,
+            };
+            if let Some(event) = event {
+                let agent = metrics::agent_client(&event.agent_string);
+                match event.event {
+                    "dial_failed" => metrics::PEER_DIAL_FAILURES.with_label_values(&[agent]).inc(),
+                    _ => metrics::PEER_DISCONNECTS.with_label_values(&[agent]).inc(),
+                }
+                if let Some(duration_ms) = event.duration_ms {
+                    metrics::PEER_CONNECTION_SECONDS
+                        .with_label_values(&[agent])
+                        .observe(duration_ms as f64 / 1000.0);
+                }
+                if let Some(peer_events) = &self.peer_events {
+                    peer_events.write(event);
+                }
             }
-        };
-        let _ = wtr.serialize(&record);
-        let _ = wtr.flush();
+        }
+        current
     }
 }
 
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 fn pad_millis(millis: u32) -> Option<String> {
     let m = millis.to_string();
     match m.len() {
@@ -171,92 +805,89 @@ fn pad_millis(millis: u32) -> Option<String> {
 impl Subscriber for Client {
     fn init(&mut self, network_send: mpsc::UnboundedSender<NetworkMessage>, fork_id: Vec<u8>) {
         self.network_send = Some(network_send);
-        self.fork_digest = Some(EnrForkId::from_ssz_bytes(&fork_id).unwrap().fork_digest);
+        let fork_digest = EnrForkId::from_ssz_bytes(&fork_id).unwrap().fork_digest;
+        self.fork_digest = Some(fork_digest);
+        self.chain = RefCell::new(ChainState::new(fork_digest));
     }
 
     fn discovered_peer(&self, peer: String) {
-        println!("Rust: discovered peer");
-        println!("peer={:?}", peer);
+        trace!(self.log, "Discovered peer"; "peer" => peer);
     }
 
     fn receive_gossip(&self, message_id: String, sequence_number: u64, agent_string: String, peer_id: String, topic: String, data: Vec<u8>) {
-        if topic.contains("beacon_block") || topic.contains("beacon_aggregate_and_proof") {
-            let timestamp = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                Ok(n) => format!(
-                    "{}.{}",
-                    n.as_secs(),
-                    pad_millis(n.subsec_millis()).expect("unexpected subsec_millis value")
-                ),
-                Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-            };
-            match GossipRecord::new(
-                self.num_records.get(),
-                timestamp.clone(),
-                message_id.clone(),
-                sequence_number,
-                agent_string.clone(),
-                peer_id.clone(),
-                topic.clone(),
-                data,
-            ) {
-                Ok(record) => {
-                    self.write_file(record);
-                    self.num_records.set(self.num_records.get() + 1);
-                    println!("Received gossip message-id={} at {:?}",message_id, timestamp);
-                    println!("peer id={:?}, agent-string:{:?}\n", peer_id, agent_string);
-                }
-                Err(e) => println!("Error:{}", e),
-            }
-        }
+        let arrival = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => n,
+            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+        };
+        self.handle_gossip(arrival, message_id, sequence_number, agent_string, peer_id, topic, data)
     }
 
     fn receive_rpc(&self, method: String, req_resp: u8, peer: String, data: Vec<u8>) {
-        /*println!("Rust: received rpc");
-        println!("method={:?}", method);
-        println!("req_resp={:?}", req_resp);
-        println!("peer={:?}", peer);
-        println!("data={:?}", String::from_utf8_lossy(&data));*/
-
-        let message = rpc::methods::StatusMessage{
-            fork_digest: self.fork_digest.unwrap(),
-            finalized_root: Hash256::zero(),
-            finalized_epoch: Epoch::new(0),
-            head_root: Hash256::zero(),
-            head_slot: Slot::new(0)
-        };
+        metrics::RPC_REQUESTS.with_label_values(&[&method]).inc();
 
-        let message_bytes = message.as_ssz_bytes();
+        let peer_id = match bs58::decode(peer.as_str())
+            .into_vec()
+            .ok()
+            .and_then(|bytes| MothraPeerId::from_bytes(bytes).ok())
+        {
+            Some(peer_id) => peer_id,
+            None => {
+                debug!(self.log, "Invalid peer id"; "peer" => &peer);
+                return;
+            }
+        };
+        let method = RpcMethod::from_name(&method);
 
-        if req_resp == 0 {
-            let request_id: RequestId = RequestId::Behaviour;
-            let request: Request = Request::Status(message_bytes);
-            let bytes = bs58::decode(peer.as_str()).into_vec().unwrap();
-            let peer_id = MothraPeerId::from_bytes(bytes).map_err(|_| ()).unwrap();
-            self.network_send
-                .as_ref()
-                .unwrap()
-                .send(NetworkMessage::SendRequest {
+        match req_resp {
+            RPC_CONNECTED => {
+                let event = self.peers.write().unwrap().on_connected(&peer, now_millis());
+                self.write_peer_event(event);
+                let request = Request::Status(self.chain.borrow().status().as_ssz_bytes());
+                self.send(NetworkMessage::SendRequest {
                     peer_id,
                     request,
-                    request_id
-                })
-                .unwrap_or_else(|_| println!("Could not send RPC request to the network service"));
-        } else {
-            let index = data[0];
-            let response: Response = Response::Status(message_bytes);
-            let bytes = bs58::decode(peer.as_str()).into_vec().unwrap();
-            let peer_id = MothraPeerId::from_bytes(bytes).map_err(|_| ()).unwrap();
-            self.network_send
-                .as_ref()
-                .unwrap()
-                .send(NetworkMessage::SendResponse {
-                    peer_id,
-                    response,
-                    index
-                })
-                .unwrap_or_else(|_| println!("Could not send RPC request to the network service"));
+                    request_id: RequestId::Behaviour,
+                });
+            }
+            RPC_REQUEST => {
+                let (index, request) = match data.split_first() {
+                    Some((index, request)) => (*index, request),
+                    None => return,
+                };
+                if method == RpcMethod::Goodbye {
+                    self.on_peer_goodbye(&peer, request);
+                }
+                match self.respond(method, &peer, request) {
+                    Some(Ok(responses)) => {
+                        for response in responses {
+                            self.send(NetworkMessage::SendResponse {
+                                peer_id: peer_id.clone(),
+                                response,
+                                index,
+                            });
+                        }
+                    }
+                    Some(Err(reason)) => self.send(NetworkMessage::SendError {
+                        peer_id,
+                        error: RPCResponseErrorCode::InvalidRequest,
+                        reason,
+                        index,
+                    }),
+                    None => {}
+                }
+            }
+            _ => match method {
+                RpcMethod::Status => self.on_peer_status(&peer, &data),
+                RpcMethod::MetaData => self.on_peer_meta_data(&peer, &data),
+                RpcMethod::BlocksByRange | RpcMethod::BlocksByRoot if !data.is_empty() => {
+                    match SignedBeaconBlock::<MainnetEthSpec>::from_ssz_bytes(&data) {
+                        Ok(block) => self.on_block(block, now_millis()),
+                        Err(e) => debug!(self.log, "Invalid block"; "peer" => &peer, "error" => format!("{:?}", e)),
+                    }
+                }
+                _ => {}
+            },
         }
-        
     }
 }
 
@@ -266,10 +897,19 @@ pub struct Adapter {
     network_send: mpsc::UnboundedSender<NetworkMessage>,
     network_exit_signal: exit_future::Signal,
     enr_fork_id: Option<eth2::types::EnrForkId>,
+    propagation: SharedPropagation,
+    writers: ClientWriters,
+    peers: SharedPeerTable,
+    slot_stats: SharedSlotStats,
+    participation: SharedParticipation,
+    peer_snapshots: Option<PeerSnapshots>,
+    connection_watch: Option<ConnectionWatch>,
+    peer_events: Option<WriterHandle<PeerEvent>>,
     log: slog::Logger,
 }
 
 impl Adapter {
+    /// Fails if `testnet_dir` cannot be loaded.
     pub fn new(
         runtime: &runtime::Runtime,
         client_name: String,
@@ -278,8 +918,9 @@ impl Adapter {
         testnet_dir: Option<PathBuf>,
         mut enrs: Vec<String>,
         arg_matches: &ArgMatches<'_>,
+        feed: Option<Feed>,
         log: slog::Logger,
-    ) -> Self {
+    ) -> Result<Self, String> {
         let mut mothra_log = log.clone();
 
         // get mothra subcommand args matches
@@ -338,7 +979,7 @@ impl Adapter {
             }
             _ => {
                 // Option: Learn fork_id from supplied testnet_dir
-                match get_fork_id_from_dir(testnet_dir) {
+                match get_fork_id_from_dir(testnet_dir.clone())? {
                     Some(enr_fork_id) => {
                         // configure gossip topics
                         config.network_config.topics = create_topic_ids(enr_fork_id.clone());
@@ -348,22 +989,101 @@ impl Adapter {
                 }
             }
         };
-        let mut client = Box::new(Client::new()) as Box<dyn Subscriber + Send>;
+        let output_format = arg_matches
+            .value_of("output-format")
+            .unwrap_or("csv")
+            .parse::<OutputFormat>()
+            .expect("Invalid output format");
+        let output_dir = dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".imp");
+
+        let attnets = parse_attnets(arg_matches.value_of("attnets").unwrap_or("none"))
+            .map_err(|e| format!("Invalid attnets: {}", e))?;
+        if let Some(enr_fork_id) = &enr_fork_id {
+            config
+                .network_config
+                .topics
+                .extend(create_attestation_topic_ids(enr_fork_id.clone(), &attnets));
+        }
+        let meta_data_path = output_dir.join("metadata.json");
+        let local_meta_data = LocalMetaData::load_or_update(&meta_data_path, attnets)
+            .map_err(|e| format!("Failed to load the local MetaData {:?}: {}", meta_data_path, e))?;
+        info!(
+            log,
+            "Advertising attestation subnets";
+            "attnets" => format!("{:?}", local_meta_data.attnets),
+            "seq_number" => local_meta_data.seq_number
+        );
+        // prefer the genesis state of the testnet dir over the presets
+        let genesis_time = get_genesis_time_from_dir(testnet_dir)?.or_else(|| {
+            enr_fork_id
+                .as_ref()
+                .and_then(|x| get_genesis_time(&hex::encode(x.fork_digest)))
+        });
+        match genesis_time {
+            Some(genesis_time) => info!(log, "Genesis time: {}", genesis_time),
+            None => warn!(log, "Unknown genesis time, slot offsets will not be recorded"),
+        }
+        let payload_archive = arg_matches.value_of("payload-archive").and_then(|dir| {
+            match PayloadArchive::open(&PathBuf::from(dir)) {
+                Ok(payload_archive) => Some(payload_archive),
+                Err(e) => {
+                    warn!(log, "Failed to open payload archive {}: {}", dir, e);
+                    None
+                }
+            }
+        });
+        let propagation = SharedPropagation::default();
+        let peers = SharedPeerTable::default();
+        let rotation = Rotation::from_arg_matches(arg_matches)?;
+        let (peer_events, peer_events_handle) =
+            spawn_event_writer("peer_events", &output_dir, rotation, &log);
+        let peer_snapshots = PeerSnapshots {
+            peers: peers.clone(),
+            dir: output_dir.clone(),
+            output_format,
+            interval: Duration::from_secs(
+                arg_matches
+                    .value_of("peer-snapshot-interval")
+                    .unwrap_or("60")
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .expect("Invalid peer snapshot interval, must be at least 1"),
+            ),
+        };
+        // blocks after the attestation deadline, a third into the slot, are late
+        let slot_stats = Arc::new(RwLock::new(SlotStats::new(
+            get_chain_spec().milliseconds_per_slot / 3,
+        )));
+        let participation = SharedParticipation::default();
+        let (client, writers) = Client::new(
+            &output_dir,
+            output_format,
+            rotation,
+            feed,
+            genesis_time,
+            propagation.clone(),
+            payload_archive,
+            peers.clone(),
+            peer_events.clone(),
+            slot_stats.clone(),
+            participation.clone(),
+            log.clone(),
+        );
+        let mut client = Box::new(client) as Box<dyn Subscriber + Send>;
         let (network_exit_signal, exit) = exit_future::signal();
         let task_executor = TaskExecutor::new(
             runtime.handle().clone(),
             exit,
             log.new(o!("Imp" => "TaskExecutor")),
         );
-        let meta_data = rpc::methods::MetaData::<MainnetEthSpec>{
-            seq_number: 1,
-            attnets: eth2::libp2p::types::EnrBitfield::<MainnetEthSpec>::default()
-        };
-        let meta_data_bytes = meta_data.as_ssz_bytes();
-        let ping_data = rpc::methods::Ping{
-            data: 1,
-        };
-        let ping_data_bytes = ping_data.as_ssz_bytes();
+        // mothra takes the MetaData and Ping as ssz bytes when it starts and
+        // builds the ENR itself, so the attnets reach our MetaData and topic
+        // subscriptions but not the ENR.
+        let meta_data_bytes = local_meta_data.meta_data().as_ssz_bytes();
+        let ping_data_bytes = local_meta_data.ping().as_ssz_bytes();
         // instantiate mothra
         let (network_globals, network_send) = runtime
             .handle()
@@ -380,16 +1100,67 @@ impl Adapter {
             })
             .unwrap();
 
-        Adapter {
+        let connection_watch = ConnectionWatch {
+            network_globals: network_globals.clone(),
+            peers: peers.clone(),
+            peer_events,
+        };
+
+        Ok(Adapter {
             network_globals,
             network_send,
             network_exit_signal,
             enr_fork_id,
+            propagation,
+            writers,
+            peers,
+            slot_stats,
+            participation,
+            peer_snapshots: Some(peer_snapshots),
+            connection_watch: Some(connection_watch),
+            peer_events: peer_events_handle,
             log,
-        }
+        })
+    }
+
+    /// Returns a handle to the gossip seen-cache.
+    pub fn propagation(&self) -> SharedPropagation {
+        self.propagation.clone()
+    }
+
+    /// Returns a handle to the slot and proposer statistics.
+    pub fn slot_stats(&self) -> SharedSlotStats {
+        self.slot_stats.clone()
+    }
+
+    /// Returns a handle to the attestation participation estimates.
+    pub fn participation(&self) -> SharedParticipation {
+        self.participation.clone()
     }
 
+    /// Returns a handle to the peer table.
+    pub fn peers(&self) -> SharedPeerTable {
+        self.peers.clone()
+    }
+
+    /// Takes the task writing peer table snapshots, to be spawned by the caller.
+    pub fn take_peer_snapshots(&mut self) -> Option<PeerSnapshots> {
+        self.peer_snapshots.take()
+    }
+
+    /// Takes the task recording disconnects and failed dials, to be spawned
+    /// by the caller.
+    pub fn take_connection_watch(&mut self) -> Option<ConnectionWatch> {
+        self.connection_watch.take()
+    }
+
+    /// Stops mothra and flushes the records still queued for writing.
     pub fn close(self) -> Result<(), ()> {
-        self.network_exit_signal.fire()
+        let result = self.network_exit_signal.fire();
+        self.writers.close();
+        if let Some(peer_events) = self.peer_events {
+            peer_events.close();
+        }
+        result
     }
 }
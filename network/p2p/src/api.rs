@@ -0,0 +1,255 @@
+use crate::crawler::{NodeQuery, SharedCrawlerState};
+use crate::divergence::divergences;
+use crate::feed::Feed;
+use crate::http::{self, error_response, json_response, query_params};
+use crate::participation::{Participation, SharedParticipation};
+use crate::peers::{PeerTable, SharedPeerTable};
+use crate::propagation::SharedPropagation;
+use crate::slots::{SharedSlotStats, SlotStats};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+use types::events::Events;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// State exposed by the read-only http api.
+#[derive(Default)]
+pub struct ApiState {
+    pub crawler: Option<SharedCrawlerState>,
+    pub feed: Option<Feed>,
+    pub propagation: Option<SharedPropagation>,
+    pub peers: Option<SharedPeerTable>,
+    pub slot_stats: Option<SharedSlotStats>,
+    pub participation: Option<SharedParticipation>,
+}
+
+/// Serves the api until a shutdown message is received.
+pub async fn serve(
+    address: SocketAddr,
+    state: Arc<ApiState>,
+    shutdown_rx: watch::Receiver<Events>,
+    log: slog::Logger,
+) {
+    let handler = move |request| handle_request(state.clone(), request);
+    http::serve("API", address, handler, shutdown_rx, log).await
+}
+
+async fn handle_request(
+    state: Arc<ApiState>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET {
+        return Ok(error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only GET is supported",
+        ));
+    }
+    let params = query_params(&request);
+    let segments: Vec<&str> = request
+        .uri()
+        .path()
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .collect();
+    let response = match segments.as_slice() {
+        ["nodes"] => with_crawler(&state, |crawler| match node_query(&params) {
+            Ok(query) => json_response(&crawler.nodes(&query)),
+            Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+        }),
+        ["nodes", node_id] => with_crawler(&state, |crawler| match crawler.node(node_id) {
+            Some(node) => json_response(&node),
+            None => error_response(StatusCode::NOT_FOUND, "unknown node id"),
+        }),
+        ["forks"] => with_crawler(&state, |crawler| json_response(&crawler.forks())),
+        ["subnets"] => with_crawler(&state, |crawler| {
+            json_response(&crawler.subnets(params.get("fork_digest").map(|x| x.as_str())))
+        }),
+        ["stats"] => with_crawler(&state, |crawler| json_response(&crawler.stats())),
+        ["propagation"] => with_propagation(&state, |propagation| {
+            match parse_usize(&params, "limit", DEFAULT_PAGE_SIZE) {
+                Ok(limit) => json_response(&serde_json::json!({
+                    "stats": propagation.stats(),
+                    "first_deliverers": propagation.first_deliverers(limit.min(MAX_PAGE_SIZE)),
+                })),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+            }
+        }),
+        ["propagation", message_id] => with_propagation(&state, |propagation| {
+            match propagation.message(message_id) {
+                Some(message) => json_response(message),
+                None => error_response(StatusCode::NOT_FOUND, "unknown message id"),
+            }
+        }),
+        ["peers"] => with_peers(&state, |peers| {
+            let page = parse_usize(&params, "offset", 0).and_then(|offset| {
+                parse_usize(&params, "limit", DEFAULT_PAGE_SIZE).map(|limit| (offset, limit))
+            });
+            match page {
+                Ok((offset, limit)) => {
+                    let matches = peers.peers(params.get("client").map(|x| x.as_str()));
+                    json_response(&serde_json::json!({
+                        "total": matches.len(),
+                        "offset": offset,
+                        "limit": limit.min(MAX_PAGE_SIZE),
+                        "peers": matches
+                            .into_iter()
+                            .skip(offset)
+                            .take(limit.min(MAX_PAGE_SIZE))
+                            .collect::<Vec<_>>(),
+                    }))
+                }
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+            }
+        }),
+        ["peers", peer_id] => with_peers(&state, |peers| match peers.peer(peer_id) {
+            Some(peer) => json_response(peer),
+            None => error_response(StatusCode::NOT_FOUND, "unknown peer id"),
+        }),
+        ["checkpoints"] => with_peers(&state, |peers| {
+            let groups = peers.checkpoints();
+            json_response(&serde_json::json!({
+                "divergences": divergences(&groups),
+                "groups": groups,
+            }))
+        }),
+        ["connections"] => with_peers(&state, |peers| json_response(&peers.connection_stats())),
+        ["epochs"] => with_slot_stats(&state, |slot_stats| {
+            match parse_usize(&params, "limit", DEFAULT_PAGE_SIZE) {
+                Ok(limit) => json_response(&slot_stats.epochs(limit.min(MAX_PAGE_SIZE))),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+            }
+        }),
+        ["proposers"] => with_slot_stats(&state, |slot_stats| {
+            match parse_usize(&params, "limit", DEFAULT_PAGE_SIZE) {
+                Ok(limit) => json_response(&slot_stats.proposers(limit.min(MAX_PAGE_SIZE))),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+            }
+        }),
+        ["participation"] => with_participation(&state, |participation| {
+            match parse_usize(&params, "limit", DEFAULT_PAGE_SIZE) {
+                Ok(limit) => json_response(&participation.epochs(limit.min(MAX_PAGE_SIZE))),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+            }
+        }),
+        ["events"] => match &state.feed {
+            Some(feed) => {
+                let kinds = params
+                    .get("types")
+                    .map(|x| x.split(',').map(|x| x.to_string()).collect())
+                    .unwrap_or_default();
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/event-stream")
+                    .header(CACHE_CONTROL, "no-cache")
+                    .body(Body::wrap_stream(feed.sse_stream(kinds)))
+                    .expect("valid response")
+            }
+            None => error_response(StatusCode::NOT_FOUND, "the feed is not enabled"),
+        },
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+    Ok(response)
+}
+
+fn with_crawler<F>(state: &ApiState, f: F) -> Response<Body>
+where
+    F: FnOnce(&crate::crawler::CrawlerState) -> Response<Body>,
+{
+    match &state.crawler {
+        Some(crawler) => match crawler.read() {
+            Ok(crawler) => f(&crawler),
+            Err(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "crawler state poisoned"),
+        },
+        None => error_response(StatusCode::NOT_FOUND, "the crawler is not running"),
+    }
+}
+
+fn parse_usize(params: &HashMap<String, String>, name: &str, default: usize) -> Result<usize, String> {
+    match params.get(name) {
+        Some(x) => x
+            .parse::<usize>()
+            .map_err(|_| format!("invalid {}: {}", name, x)),
+        None => Ok(default),
+    }
+}
+
+fn with_propagation<F>(state: &ApiState, f: F) -> Response<Body>
+where
+    F: FnOnce(&crate::propagation::Propagation) -> Response<Body>,
+{
+    match &state.propagation {
+        Some(propagation) => match propagation.read() {
+            Ok(propagation) => f(&propagation),
+            Err(_) => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "propagation state poisoned",
+            ),
+        },
+        None => error_response(StatusCode::NOT_FOUND, "the agent is not running"),
+    }
+}
+
+fn with_peers<F>(state: &ApiState, f: F) -> Response<Body>
+where
+    F: FnOnce(&PeerTable) -> Response<Body>,
+{
+    match &state.peers {
+        Some(peers) => match peers.read() {
+            Ok(peers) => f(&peers),
+            Err(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "peer table poisoned"),
+        },
+        None => error_response(StatusCode::NOT_FOUND, "the agent is not running"),
+    }
+}
+
+fn with_slot_stats<F>(state: &ApiState, f: F) -> Response<Body>
+where
+    F: FnOnce(&SlotStats) -> Response<Body>,
+{
+    match &state.slot_stats {
+        Some(slot_stats) => match slot_stats.read() {
+            Ok(slot_stats) => f(&slot_stats),
+            Err(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "slot stats poisoned"),
+        },
+        None => error_response(StatusCode::NOT_FOUND, "the agent is not running"),
+    }
+}
+
+fn with_participation<F>(state: &ApiState, f: F) -> Response<Body>
+where
+    F: FnOnce(&Participation) -> Response<Body>,
+{
+    match &state.participation {
+        Some(participation) => match participation.read() {
+            Ok(participation) => f(&participation),
+            Err(_) => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "participation state poisoned",
+            ),
+        },
+        None => error_response(StatusCode::NOT_FOUND, "the agent is not running"),
+    }
+}
+
+/// Parses `fork_digest`, `subnet`, `offset` and `limit` query parameters.
+fn node_query(params: &HashMap<String, String>) -> Result<NodeQuery, String> {
+    let parse = |name: &str, default: usize| parse_usize(params, name, default);
+    let subnet_id = match params.get("subnet") {
+        Some(x) => Some(
+            x.parse::<u64>()
+                .map_err(|_| format!("invalid subnet: {}", x))?,
+        ),
+        None => None,
+    };
+    Ok(NodeQuery {
+        fork_digest: params.get("fork_digest").cloned(),
+        subnet_id,
+        offset: parse("offset", 0)?,
+        limit: parse("limit", DEFAULT_PAGE_SIZE)?.min(MAX_PAGE_SIZE),
+    })
+}
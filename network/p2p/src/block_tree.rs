@@ -0,0 +1,186 @@
+use eth2::types::{Hash256, Slot};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Slots behind the head after which a block is settled: it is either on
+/// the canonical chain or orphaned, and is dropped from the tree.
+pub const SETTLE_DEPTH: u64 = 64;
+
+struct Node {
+    parent_root: Hash256,
+    slot: Slot,
+    proposer_index: u64,
+}
+
+/// A reorg or orphaned block, as written to `block_events.jsonl`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct BlockEvent {
+    /// Unix time in milliseconds.
+    pub timestamp: u64,
+    /// `reorg` or `orphaned`.
+    pub event: &'static str,
+    pub slot: u64,
+    pub root: String,
+    pub proposer_index: Option<u64>,
+    /// Head before a reorg.
+    pub old_head_root: Option<String>,
+    pub old_head_slot: Option<u64>,
+    /// Last block both branches of a reorg share.
+    pub common_ancestor_root: Option<String>,
+    pub common_ancestor_slot: Option<u64>,
+    /// Blocks of the old branch that left the canonical chain.
+    pub depth: Option<u64>,
+}
+
+/// Tree of recent blocks linked by `parent_root`.
+///
+/// imp runs no fork choice, so the head is the block with the highest slot,
+/// the first one seen winning ties, like `ChainState`. Blocks whose parent
+/// was never seen start a new root of the tree.
+#[derive(Default)]
+pub struct BlockTree {
+    nodes: HashMap<Hash256, Node>,
+    head: Option<Hash256>,
+}
+
+impl BlockTree {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn head(&self) -> Option<(Hash256, Slot)> {
+        self.head
+            .and_then(|root| self.nodes.get(&root).map(|node| (root, node.slot)))
+    }
+
+    /// Adds a block and returns the reorg and orphan events it caused.
+    pub fn insert(
+        &mut self,
+        root: Hash256,
+        parent_root: Hash256,
+        slot: Slot,
+        proposer_index: u64,
+        now_ms: u64,
+    ) -> Vec<BlockEvent> {
+        if self.nodes.contains_key(&root) {
+            return vec![];
+        }
+        // too old to change anything, and would be pruned right away
+        if let Some((_, head_slot)) = self.head() {
+            if slot + SETTLE_DEPTH < head_slot {
+                return vec![];
+            }
+        }
+        self.nodes.insert(
+            root,
+            Node {
+                parent_root,
+                slot,
+                proposer_index,
+            },
+        );
+        let old_head = match self.head() {
+            Some(old_head) => old_head,
+            None => {
+                self.head = Some(root);
+                return vec![];
+            }
+        };
+        if slot <= old_head.1 {
+            return vec![];
+        }
+        self.head = Some(root);
+        let mut events = vec![];
+        if let Some(event) = self.reorg(old_head, root, now_ms) {
+            events.push(event);
+        }
+        events.extend(self.settle(now_ms));
+        events
+    }
+
+    /// Returns the ancestors of `root` in the tree, starting with `root`.
+    fn ancestors(&self, root: Hash256) -> Vec<(Hash256, Slot)> {
+        let mut ancestors = vec![];
+        let mut root = root;
+        while let Some(node) = self.nodes.get(&root) {
+            ancestors.push((root, node.slot));
+            root = node.parent_root;
+        }
+        ancestors
+    }
+
+    /// Describes the switch from `old_head` to `new_head`, unless the new
+    /// head extends the old one. Without a common ancestor in the tree the
+    /// branches cannot be compared, so nothing is reported.
+    fn reorg(&self, old_head: (Hash256, Slot), new_head: Hash256, now_ms: u64) -> Option<BlockEvent> {
+        let new_branch: HashSet<Hash256> = self
+            .ancestors(new_head)
+            .into_iter()
+            .map(|(root, _)| root)
+            .collect();
+        if new_branch.contains(&old_head.0) {
+            return None;
+        }
+        let old_branch = self.ancestors(old_head.0);
+        let depth = old_branch
+            .iter()
+            .position(|(root, _)| new_branch.contains(root))?;
+        let (ancestor_root, ancestor_slot) = old_branch[depth];
+        let node = &self.nodes[&new_head];
+        Some(BlockEvent {
+            timestamp: now_ms,
+            event: "reorg",
+            slot: node.slot.as_u64(),
+            root: format!("{:?}", new_head),
+            proposer_index: Some(node.proposer_index),
+            old_head_root: Some(format!("{:?}", old_head.0)),
+            old_head_slot: Some(old_head.1.as_u64()),
+            common_ancestor_root: Some(format!("{:?}", ancestor_root)),
+            common_ancestor_slot: Some(ancestor_slot.as_u64()),
+            depth: Some(depth as u64),
+        })
+    }
+
+    /// Drops the blocks more than `SETTLE_DEPTH` slots behind the head and
+    /// reports those that are not ancestors of it. Where a missed block cuts
+    /// the canonical chain short, older blocks cannot be judged and are
+    /// dropped silently.
+    fn settle(&mut self, now_ms: u64) -> Vec<BlockEvent> {
+        let (head_root, head_slot) = match self.head() {
+            Some(head) => head,
+            None => return vec![],
+        };
+        let settled: Vec<Hash256> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.slot + SETTLE_DEPTH < head_slot)
+            .map(|(root, _)| *root)
+            .collect();
+        if settled.is_empty() {
+            return vec![];
+        }
+        let ancestors = self.ancestors(head_root);
+        let canonical_from = ancestors.last().map(|(_, slot)| *slot).unwrap_or(head_slot);
+        let canonical: HashSet<Hash256> = ancestors.into_iter().map(|(root, _)| root).collect();
+        let mut events = vec![];
+        for root in settled {
+            let node = self.nodes.remove(&root).expect("settled nodes are in the tree");
+            if canonical_from <= node.slot && !canonical.contains(&root) {
+                events.push(BlockEvent {
+                    timestamp: now_ms,
+                    event: "orphaned",
+                    slot: node.slot.as_u64(),
+                    root: format!("{:?}", root),
+                    proposer_index: Some(node.proposer_index),
+                    ..Default::default()
+                });
+            }
+        }
+        events.sort_by_key(|event| event.slot);
+        events
+    }
+}
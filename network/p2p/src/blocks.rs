@@ -0,0 +1,86 @@
+use eth2::types::{Hash256, MainnetEthSpec, SignedBeaconBlock, Slot};
+use std::collections::{BTreeMap, HashMap};
+
+/// Most blocks a single BlocksByRange or BlocksByRoot request may ask for.
+pub const MAX_REQUEST_BLOCKS: u64 = 1024;
+
+type Block = SignedBeaconBlock<MainnetEthSpec>;
+
+/// Bounded store of the blocks imp received through gossip or RPC. When it
+/// is full the blocks of the oldest slot are dropped first.
+pub struct BlockStore {
+    capacity: usize,
+    blocks: HashMap<Hash256, Block>,
+    by_slot: BTreeMap<Slot, Vec<Hash256>>,
+}
+
+impl BlockStore {
+    pub fn new(capacity: usize) -> Self {
+        BlockStore {
+            capacity,
+            blocks: HashMap::new(),
+            by_slot: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn get(&self, root: &Hash256) -> Option<&Block> {
+        self.blocks.get(root)
+    }
+
+    /// Stores `block` under `root`. Returns false if it was already known.
+    pub fn insert(&mut self, root: Hash256, block: Block) -> bool {
+        if self.blocks.contains_key(&root) {
+            return false;
+        }
+        self.by_slot.entry(block.message.slot).or_default().push(root);
+        self.blocks.insert(root, block);
+        while self.blocks.len() > self.capacity {
+            let oldest = match self.by_slot.keys().next() {
+                Some(slot) => *slot,
+                None => break,
+            };
+            for root in self.by_slot.remove(&oldest).unwrap_or_default() {
+                self.blocks.remove(&root);
+            }
+        }
+        true
+    }
+
+    /// Answers a BlocksByRoot request. Unknown roots are skipped.
+    pub fn blocks_by_root(&self, roots: &[Hash256]) -> Vec<&Block> {
+        roots
+            .iter()
+            .take(MAX_REQUEST_BLOCKS as usize)
+            .filter_map(|root| self.blocks.get(root))
+            .collect()
+    }
+
+    /// Answers a BlocksByRange request in slot order with the blocks on the
+    /// chain leading to `head_root`, skipping empty and unknown slots. A
+    /// `step` of 0 asks for nothing.
+    pub fn blocks_by_range(&self, start_slot: u64, count: u64, step: u64, head_root: Hash256) -> Vec<&Block> {
+        if step == 0 {
+            return vec![];
+        }
+        let mut canonical = HashMap::new();
+        let mut root = head_root;
+        while let Some(block) = self.blocks.get(&root) {
+            canonical.insert(block.message.slot, root);
+            root = block.message.parent_root;
+        }
+        (0..count.min(MAX_REQUEST_BLOCKS))
+            .filter_map(|i| i.checked_mul(step).and_then(|offset| start_slot.checked_add(offset)))
+            .map(Slot::new)
+            .filter_map(|slot| canonical.get(&slot))
+            .filter_map(|root| self.blocks.get(root))
+            .collect()
+    }
+}
@@ -0,0 +1,66 @@
+use eth2::libp2p::rpc::methods::StatusMessage;
+use eth2::types::{Epoch, EthSpec, Hash256, MainnetEthSpec, Slot};
+
+/// Best head and finalized checkpoint seen on the network.
+///
+/// imp does not verify blocks, so this is only what gossip and peers' Status
+/// messages claim. It is good enough to send a plausible Status of our own.
+pub struct ChainState {
+    fork_digest: [u8; 4],
+    pub head_root: Hash256,
+    pub head_slot: Slot,
+    pub finalized_root: Hash256,
+    pub finalized_epoch: Epoch,
+}
+
+impl ChainState {
+    pub fn new(fork_digest: [u8; 4]) -> Self {
+        ChainState {
+            fork_digest,
+            head_root: Hash256::zero(),
+            head_slot: Slot::new(0),
+            finalized_root: Hash256::zero(),
+            finalized_epoch: Epoch::new(0),
+        }
+    }
+
+    /// Moves the head to a gossip block if it is newer than the current head.
+    pub fn on_block(&mut self, root: Hash256, slot: Slot) {
+        if slot > self.head_slot {
+            self.head_root = root;
+            self.head_slot = slot;
+        }
+    }
+
+    /// Takes the head and finalized checkpoint of a peer on the same fork.
+    /// Heads beyond `current_slot` and checkpoints beyond its epoch are
+    /// ignored, so a single peer cannot push us into the future.
+    pub fn on_status(&mut self, status: &StatusMessage, current_slot: Option<Slot>) {
+        if status.fork_digest != self.fork_digest {
+            return;
+        }
+        let current_epoch =
+            current_slot.map(|current_slot| current_slot.epoch(MainnetEthSpec::slots_per_epoch()));
+        let plausible_checkpoint = current_epoch.map_or(true, |current_epoch| {
+            status.finalized_epoch <= current_epoch
+        });
+        if plausible_checkpoint && status.finalized_epoch > self.finalized_epoch {
+            self.finalized_root = status.finalized_root;
+            self.finalized_epoch = status.finalized_epoch;
+        }
+        let plausible_head = current_slot.map_or(true, |current_slot| status.head_slot <= current_slot);
+        if plausible_head {
+            self.on_block(status.head_root, status.head_slot);
+        }
+    }
+
+    pub fn status(&self) -> StatusMessage {
+        StatusMessage {
+            fork_digest: self.fork_digest,
+            finalized_root: self.finalized_root,
+            finalized_epoch: self.finalized_epoch,
+            head_root: self.head_root,
+            head_slot: self.head_slot,
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use arrow::datatypes::SchemaRef;
+use arrow::error::Result as ArrowResult;
+use arrow::record_batch::RecordBatch;
+use crate::rotation::Rotation;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Number of buffered records written as one parquet row group.
+const ROW_GROUP_SIZE: usize = 4096;
+
+/// A record that can be written as a typed arrow column set.
+pub trait ParquetRecord: Sized {
+    fn schema() -> SchemaRef;
+    fn to_record_batch(records: &[Self]) -> ArrowResult<RecordBatch>;
+}
+
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    file: File,
+    opened_at: Instant,
+}
+
+/// Buffers records and writes them to `<dir>/<name>.parquet` in row groups,
+/// when a group is full or on `flush`. Once the rollover policy is due the
+/// file is closed and archived, and the next row group starts a new file.
+pub struct ParquetSink<R: ParquetRecord> {
+    path: PathBuf,
+    rotation: Rotation,
+    buffer: Vec<R>,
+    current: Option<OpenFile>,
+}
+
+impl<R: ParquetRecord> ParquetSink<R> {
+    pub fn create(dir: &Path, name: &str, rotation: Rotation) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.parquet", name));
+        Ok(ParquetSink {
+            path,
+            rotation,
+            buffer: Vec::with_capacity(ROW_GROUP_SIZE),
+            current: None,
+        })
+    }
+
+    pub fn write(&mut self, record: R) -> io::Result<()> {
+        self.buffer.push(record);
+        if self.buffer.len() >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// Writes all buffered records as a row group.
+    fn write_row_group(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let batch = R::to_record_batch(&self.buffer).map_err(to_io_error)?;
+        self.buffer.clear();
+        if self.current.is_none() {
+            self.current = Some(self.open()?);
+        }
+        if let Some(current) = self.current.as_mut() {
+            current.writer.write(&batch).map_err(to_io_error)?;
+        }
+        self.roll_over_if_due()
+    }
+
+    /// Writes the buffered records as a possibly partial row group and
+    /// rolls the file over if due. Called on the writer's flush tick, so
+    /// low-volume datasets reach the disk and follow time-based rollover.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_row_group()?;
+        self.roll_over_if_due()
+    }
+
+    fn roll_over_if_due(&mut self) -> io::Result<()> {
+        let due = match &self.current {
+            Some(current) => {
+                let bytes = current.file.metadata()?.len();
+                self.rotation.rollover.is_due(bytes, current.opened_at.elapsed())
+            }
+            None => false,
+        };
+        if due {
+            self.close_file()?;
+            self.rotation.archive(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes buffered records and the parquet footer.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.write_row_group()?;
+        self.close_file()
+    }
+
+    fn open(&self) -> io::Result<OpenFile> {
+        let file = File::create(&self.path)?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(file.try_clone()?, R::schema(), Some(props))
+            .map_err(to_io_error)?;
+        Ok(OpenFile {
+            writer,
+            file,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn close_file(&mut self) -> io::Result<()> {
+        match self.current.take() {
+            Some(mut current) => current.writer.close().map(|_| ()).map_err(to_io_error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: ParquetRecord> Drop for ParquetSink<R> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Parses a `<secs>.<millis>` unix timestamp as written in gossip records.
+pub fn parse_unix_millis(timestamp: &str) -> Option<i64> {
+    let mut parts = timestamp.splitn(2, '.');
+    let secs = parts.next()?.parse::<i64>().ok()?;
+    let millis = match parts.next() {
+        Some(millis) => millis.parse::<i64>().ok()?,
+        None => 0,
+    };
+    Some(secs * 1000 + millis)
+}
@@ -1,7 +1,20 @@
+use crate::feed::{Feed, ENR_EVENT};
+use crate::forks::{ForkIndex, ForkStat};
+use crate::metrics;
+use crate::columnar::{ParquetRecord, ParquetSink};
+use crate::output::{merge_json, OutputFormat, Sink};
+use crate::rotation::Rotation;
 use csv;
 #[macro_use]
 use serde_derive::{Serialize};
-use chrono::Local;
+use arrow::array::{
+    ArrayRef, ListBuilder, StringBuilder, TimestampMillisecondBuilder, UInt16Builder,
+    UInt32Builder, UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::Result as ArrowResult;
+use arrow::record_batch::RecordBatch;
+use chrono::{Local, NaiveDateTime, TimeZone};
 use clap::{App, AppSettings, Arg, ArgMatches};
 
 use discv5::{
@@ -19,13 +32,14 @@ use futures::prelude::*;
 use rand::Rng;
 use slog::{debug, info, o, trace, warn};
 use std::any::type_name;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
-use std::fs::OpenOptions;
+use std::fs::File;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::watch;
 use types::events::Events;
 
@@ -45,6 +59,8 @@ struct EnrEntry {
     seq_no: String,
     subnet_ids: String,
     enr: String,
+    #[serde(skip_serializing)]
+    attnets: Vec<u64>,
 }
 
 impl EnrEntry {
@@ -86,7 +102,8 @@ impl EnrEntry {
             ),
             _ => ("".to_string(), "".to_string(), "".to_string()),
         };
-        let subnet_ids = format!("{:?}", get_attnets_from_enr(enr));
+        let attnets = get_attnets_from_enr(enr);
+        let subnet_ids = format!("{:?}", attnets);
         EnrEntry {
             node_id: node_id.clone(),
             peer_id: peer_id.clone(),
@@ -102,6 +119,7 @@ impl EnrEntry {
             seq_no: seq_no.clone(),
             subnet_ids: subnet_ids.clone(),
             enr: enr.to_base64(),
+            attnets,
         }
     }
 }
@@ -130,6 +148,118 @@ impl EnrRecord {
     }
 }
 
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d][%H:%M:%S";
+
+impl ParquetRecord for EnrRecord {
+    fn schema() -> SchemaRef {
+        let optional_u16 = |name| Field::new(name, DataType::UInt16, true);
+        Arc::new(Schema::new(vec![
+            Field::new("index", DataType::UInt32, false),
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("node_id", DataType::Utf8, false),
+            Field::new("peer_id", DataType::Utf8, false),
+            Field::new("ip4", DataType::Utf8, true),
+            optional_u16("tcp4"),
+            optional_u16("udp4"),
+            Field::new("ip6", DataType::Utf8, true),
+            optional_u16("tcp6"),
+            optional_u16("udp6"),
+            Field::new("next_fork_version", DataType::Utf8, true),
+            Field::new("next_fork_epoch", DataType::UInt64, true),
+            Field::new("fork_digest", DataType::Utf8, true),
+            Field::new("seq_no", DataType::UInt64, false),
+            Field::new(
+                "subnet_ids",
+                DataType::List(Box::new(Field::new("item", DataType::UInt64, true))),
+                false,
+            ),
+            Field::new("enr", DataType::Utf8, false),
+        ]))
+    }
+
+    fn to_record_batch(records: &[Self]) -> ArrowResult<RecordBatch> {
+        let n = records.len();
+        let mut index = UInt32Builder::new(n);
+        let mut timestamp = TimestampMillisecondBuilder::new(n);
+        let mut node_id = StringBuilder::new(n);
+        let mut peer_id = StringBuilder::new(n);
+        let mut ip4 = StringBuilder::new(n);
+        let mut tcp4 = UInt16Builder::new(n);
+        let mut udp4 = UInt16Builder::new(n);
+        let mut ip6 = StringBuilder::new(n);
+        let mut tcp6 = UInt16Builder::new(n);
+        let mut udp6 = UInt16Builder::new(n);
+        let mut next_fork_version = StringBuilder::new(n);
+        let mut next_fork_epoch = UInt64Builder::new(n);
+        let mut fork_digest = StringBuilder::new(n);
+        let mut seq_no = UInt64Builder::new(n);
+        let mut subnet_ids = ListBuilder::new(UInt64Builder::new(n));
+        let mut enr = StringBuilder::new(n);
+
+        // empty strings are how EnrEntry encodes missing values
+        fn append_str(builder: &mut StringBuilder, value: &str) -> ArrowResult<()> {
+            match value {
+                "" => builder.append_null(),
+                _ => builder.append_value(value),
+            }
+        }
+
+        for record in records {
+            let entry = &record.enr;
+            index.append_value(record.index)?;
+            timestamp.append_option(
+                NaiveDateTime::parse_from_str(&record.timestamp, TIMESTAMP_FORMAT)
+                    .ok()
+                    .and_then(|x| Local.from_local_datetime(&x).single())
+                    .map(|x| x.timestamp_millis()),
+            )?;
+            node_id.append_value(&entry.node_id)?;
+            peer_id.append_value(&entry.peer_id)?;
+            append_str(&mut ip4, &entry.ip4)?;
+            tcp4.append_option(entry.tcp4.parse::<u16>().ok())?;
+            udp4.append_option(entry.udp4.parse::<u16>().ok())?;
+            append_str(&mut ip6, &entry.ip6)?;
+            tcp6.append_option(entry.tcp6.parse::<u16>().ok())?;
+            udp6.append_option(entry.udp6.parse::<u16>().ok())?;
+            append_str(&mut next_fork_version, &entry.next_fork_version)?;
+            next_fork_epoch.append_option(entry.next_fork_epoch.parse::<u64>().ok())?;
+            append_str(&mut fork_digest, &entry.fork_digest)?;
+            seq_no.append_value(entry.seq_no.parse::<u64>().unwrap_or_default())?;
+            for subnet_id in &entry.attnets {
+                subnet_ids.values().append_value(*subnet_id)?;
+            }
+            subnet_ids.append(true)?;
+            enr.append_value(&entry.enr)?;
+        }
+
+        RecordBatch::try_new(
+            Self::schema(),
+            vec![
+                Arc::new(index.finish()) as ArrayRef,
+                Arc::new(timestamp.finish()),
+                Arc::new(node_id.finish()),
+                Arc::new(peer_id.finish()),
+                Arc::new(ip4.finish()),
+                Arc::new(tcp4.finish()),
+                Arc::new(udp4.finish()),
+                Arc::new(ip6.finish()),
+                Arc::new(tcp6.finish()),
+                Arc::new(udp6.finish()),
+                Arc::new(next_fork_version.finish()),
+                Arc::new(next_fork_epoch.finish()),
+                Arc::new(fork_digest.finish()),
+                Arc::new(seq_no.finish()),
+                Arc::new(subnet_ids.finish()),
+                Arc::new(enr.finish()),
+            ],
+        )
+    }
+}
+
 #[derive(Serialize, Default)]
 struct FindNodeRecord {
     index: u32,
@@ -158,26 +288,202 @@ struct EnrAddedRecord {
     replaced: EnrEntry,
 }
 
+/// Statistics about the running crawl.
+#[derive(Serialize, Default, Clone)]
+pub struct CrawlStats {
+    pub started_at: u64,
+    pub query_rounds: u64,
+    pub connected_peers: usize,
+    pub nodes: usize,
+    pub fork_digests: usize,
+}
+
+/// Conditions under which a crawl stops on its own.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CrawlLimits {
+    /// Wall-clock duration of the crawl
+    pub duration: Option<Duration>,
+    /// Number of unique nodes to discover
+    pub max_nodes: Option<usize>,
+    /// Number of consecutive query rounds without a new node
+    pub saturation_rounds: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    Shutdown,
+    Duration,
+    MaxNodes,
+    Saturated,
+}
+
+/// Written to `<datadir>/crawler<port>-summary.json` when a crawl stops.
+#[derive(Serialize, Clone)]
+pub struct CrawlSummary {
+    pub stop_reason: StopReason,
+    pub elapsed_secs: u64,
+    pub stats: CrawlStats,
+    pub forks: Vec<ForkStat>,
+}
+
+impl CrawlSummary {
+    /// 0 when the crawl found nodes, 2 when it found none.
+    pub fn exit_code(&self) -> i32 {
+        match self.stats.nodes {
+            0 => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// Filter and page applied to the node table.
+#[derive(Clone, Debug)]
+pub struct NodeQuery {
+    pub fork_digest: Option<String>,
+    pub subnet_id: Option<u64>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Serialize)]
+pub struct NodePage {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub nodes: Vec<serde_json::Value>,
+}
+
+/// The crawler's in-memory node table, shared with the http api.
+#[derive(Default)]
+pub struct CrawlerState {
+    enr_records: HashMap<String, EnrRecord>,
+    fork_index: ForkIndex,
+    stats: CrawlStats,
+}
+
+pub type SharedCrawlerState = Arc<RwLock<CrawlerState>>;
+
+impl CrawlerState {
+    /// Stores the latest record of each ENR and returns the records that are
+    /// new or have a new sequence number.
+    fn update(&mut self, enrs: &[Enr<CombinedKey>], timestamp: &str) -> Vec<EnrRecord> {
+        let mut updated = vec![];
+        for (index, enr) in enrs.iter().enumerate() {
+            let enr_entry = EnrEntry::new(enr);
+            self.fork_index
+                .insert(&enr_entry.node_id, &enr_entry.fork_digest);
+            let is_updated = match self.enr_records.get(&enr_entry.node_id) {
+                Some(enr_record) => enr_record.enr.seq_no != enr_entry.seq_no,
+                None => true,
+            };
+            let enr_record = EnrRecord::new(index as u32 + 1, timestamp.to_string(), enr_entry);
+            if is_updated {
+                updated.push(enr_record.clone());
+            }
+            self.enr_records
+                .insert(enr_record.enr.node_id.clone(), enr_record);
+        }
+        self.stats.nodes = self.enr_records.len();
+        self.stats.fork_digests = self.fork_index.stats().len();
+        updated
+    }
+
+    pub fn stats(&self) -> CrawlStats {
+        self.stats.clone()
+    }
+
+    pub fn forks(&self) -> Vec<ForkStat> {
+        self.fork_index.stats()
+    }
+
+    pub fn node(&self, node_id: &str) -> Option<serde_json::Value> {
+        self.enr_records
+            .get(node_id)
+            .and_then(|enr_record| merge_json(enr_record, &enr_record.enr).ok())
+    }
+
+    /// Returns the matching nodes ordered by node id.
+    pub fn nodes(&self, query: &NodeQuery) -> NodePage {
+        let mut matches: Vec<&EnrRecord> = self
+            .enr_records
+            .values()
+            .filter(|enr_record| {
+                query
+                    .fork_digest
+                    .as_ref()
+                    .map_or(true, |x| &enr_record.enr.fork_digest == x)
+                    && query
+                        .subnet_id
+                        .map_or(true, |x| enr_record.enr.attnets.contains(&x))
+            })
+            .collect();
+        matches.sort_by(|a, b| a.enr.node_id.cmp(&b.enr.node_id));
+        NodePage {
+            total: matches.len(),
+            offset: query.offset,
+            limit: query.limit,
+            nodes: matches
+                .into_iter()
+                .skip(query.offset)
+                .take(query.limit)
+                .filter_map(|enr_record| merge_json(enr_record, &enr_record.enr).ok())
+                .collect(),
+        }
+    }
+
+    /// Returns the number of nodes subscribed to each attestation subnet.
+    pub fn subnets(&self, fork_digest: Option<&str>) -> BTreeMap<u64, usize> {
+        let mut subnets = BTreeMap::new();
+        for enr_record in self.enr_records.values() {
+            if fork_digest.map_or(true, |x| enr_record.enr.fork_digest == x) {
+                for subnet_id in &enr_record.enr.attnets {
+                    *subnets.entry(*subnet_id).or_insert(0) += 1;
+                }
+            }
+        }
+        subnets
+    }
+}
+
 pub struct Crawler {
+    state: SharedCrawlerState,
+    feed: Option<Feed>,
     local_enr: Enr<CombinedKey>,
     enr_key: CombinedKey,
     socket_addr: SocketAddr,
     boot_enr_list: Vec<String>,
     config: Discv5Config,
     output_mode: String,
+    output_format: OutputFormat,
+    rotation: Rotation,
+    limits: CrawlLimits,
+    query_interval: Duration,
     fork_digest: String,
     datadir: PathBuf,
 }
 
 impl Crawler {
-    pub fn new(arg_matches: &ArgMatches<'_>, mut enrs: Vec<String>, log: slog::Logger) -> Self {
+    /// Fails if the rotation options are invalid.
+    pub fn new(
+        arg_matches: &ArgMatches<'_>,
+        mut enrs: Vec<String>,
+        log: slog::Logger,
+    ) -> Result<Self, String> {
         // get mothra subcommand args matches
         let crawler_arg_matches = &arg_matches.subcommand_matches("crawler").unwrap();
+        let rotation = Rotation::from_arg_matches(arg_matches)?;
 
         let output_mode = crawler_arg_matches
             .value_of("output-mode")
             .expect("required parameter");
 
+        let output_format = arg_matches
+            .value_of("output-format")
+            .unwrap_or("csv")
+            .parse::<OutputFormat>()
+            .expect("Invalid output format");
+
         let datadir = crawler_arg_matches
             .value_of("datadir")
             .map(PathBuf::from)
@@ -204,6 +510,23 @@ impl Crawler {
             .value_of("fork-digest")
             .expect("required parameter");
 
+        let query_interval = crawler_arg_matches
+            .value_of("query-interval")
+            .map(|x| Duration::from_secs(x.parse::<u64>().expect("validated by cli_app")))
+            .unwrap_or_else(|| Duration::from_secs(10));
+
+        let limits = CrawlLimits {
+            duration: crawler_arg_matches
+                .value_of("duration")
+                .map(|x| Duration::from_secs(x.parse::<u64>().expect("Invalid duration"))),
+            max_nodes: crawler_arg_matches
+                .value_of("max-nodes")
+                .map(|x| x.parse::<usize>().expect("Invalid max-nodes")),
+            saturation_rounds: crawler_arg_matches
+                .value_of("saturation-rounds")
+                .map(|x| x.parse::<u64>().expect("Invalid saturation-rounds")),
+        };
+
         let mut boot_enr_list = if crawler_arg_matches.is_present("boot-nodes") {
             crawler_arg_matches
                 .value_of("boot-nodes")
@@ -272,19 +595,49 @@ impl Crawler {
         // the address to listen on
         let socket_addr = SocketAddr::new(listen_address, listen_port);
 
-        Crawler {
+        let state = SharedCrawlerState::default();
+        state.write().unwrap().stats.started_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+
+        Ok(Crawler {
+            state,
+            feed: None,
             local_enr,
             enr_key,
             socket_addr,
             boot_enr_list,
             config,
             output_mode: output_mode.to_string(),
+            output_format,
+            rotation,
+            limits,
+            query_interval,
             fork_digest: fork_digest.to_string(),
             datadir,
-        }
+        })
+    }
+
+    /// Publishes new and updated records to `feed` while crawling.
+    pub fn with_feed(mut self, feed: Feed) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Returns a handle to the node table that stays valid while the crawl runs.
+    pub fn state(&self) -> SharedCrawlerState {
+        self.state.clone()
     }
 
-    pub async fn find_nodes(self, mut shutdown_rx: watch::Receiver<Events>, log: slog::Logger) {
+    /// Crawls until a shutdown message is received or one of the crawl limits
+    /// is reached, then writes the final outputs and returns a summary.
+    pub async fn find_nodes(
+        self,
+        mut shutdown_rx: watch::Receiver<Events>,
+        log: slog::Logger,
+    ) -> CrawlSummary {
+        let started_at = Instant::now();
         // construct the discv5 swarm, initializing an unused transport layer
         let mut discv5 = Discv5::new(self.local_enr, self.enr_key, self.config).unwrap();
         // start the discv5 service
@@ -335,21 +688,47 @@ impl Crawler {
             discv5.table_entries_enr().len()
         );
 
-        let output_file = match discv5.local_enr().udp() {
-            Some(x) => format!("crawler{}.csv", x),
-            _ => format!("crawler.csv"),
+        let output_name = match discv5.local_enr().udp() {
+            Some(x) => format!("crawler{}", x),
+            _ => format!("crawler"),
+        };
+        let snapshot_path = self
+            .datadir
+            .join(format!("{}.{}", output_name, self.output_format.extension()));
+        let mut rotated_at = Instant::now();
+        if self.output_mode != "none" && !self.output_format.is_streaming() {
+            // keep the snapshot of the previous run
+            if let Err(e) = self.rotation.archive(&snapshot_path) {
+                warn!(log, "Failed to archive previous snapshot: {}", e);
+            }
+        }
+        // streaming formats emit each new or updated record instead of snapshots
+        let mut stream_sink = if self.output_format.is_streaming() && self.output_mode != "none" {
+            match Sink::create(self.output_format, &self.datadir, &output_name, false) {
+                Ok(sink) => Some(sink),
+                Err(e) => {
+                    warn!(log, "Failed to open output stream: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
         };
-        let mut target_enr = "".to_string();
         let target_fork_digest = self.fork_digest;
         // construct a time interval to search for new peers.
-        let mut query_interval = tokio::time::interval(Duration::from_secs(10));
+        let mut query_interval = tokio::time::interval(self.query_interval);
         let mut output_interval = tokio::time::interval(Duration::from_secs(30));
-        let mut enr_records: HashMap<String, EnrRecord> = Default::default();
+        let crawler_state = self.state.clone();
 
         let mut enr_added_count: u64 = 0;
         let mut node_inserted_count: u64 = 0;
         let mut event_stream = discv5.event_stream().await.unwrap();
-        loop {
+        let limits = self.limits;
+        let deadline = tokio::time::delay_for(limits.duration.unwrap_or_default());
+        tokio::pin!(deadline);
+        let mut num_records_prev = 0;
+        let mut idle_rounds: u64 = 0;
+        let stop_reason = loop {
             tokio::select! {
                 x = shutdown_rx.recv() => {
                     if let Some(Events::ShutdownMessage) = x {
@@ -358,31 +737,31 @@ impl Crawler {
                             "{:?}: shutdown message received.",
                             type_name::<Crawler>()
                         );
-                        match self.output_mode.as_str() {
-                            "snapshot" => {
-                                info!(log,"Output is enabled.  Saving data to file");
-                                Crawler::write_file(&enr_records, self.datadir.join(&output_file));
-                                break;
-                            },
-                            _ => {
-                                info!(log,"Output is disabled.  Not saving to file.");
-                                break
-                            }
-                        };
+                        break StopReason::Shutdown;
                     }
                 },
+                _ = &mut deadline, if limits.duration.is_some() => {
+                    info!(log, "Crawl duration elapsed.");
+                    break StopReason::Duration;
+                },
                 _ = output_interval.next() => {
                     match self.output_mode.as_str() {
                         "snapshot" => {
                             info!(log,"Output is enabled.  Saving data to file");
-                            Crawler::write_file(&enr_records, self.datadir.join(&output_file));
+                            let snapshot_bytes = std::fs::metadata(&snapshot_path).map(|x| x.len()).unwrap_or(0);
+                            if self.rotation.rollover.is_due(snapshot_bytes, rotated_at.elapsed()) {
+                                if let Err(e) = self.rotation.archive(&snapshot_path) {
+                                    warn!(log, "Failed to rotate snapshot: {}", e);
+                                }
+                                rotated_at = Instant::now();
+                            }
+                            Crawler::write_snapshot(&crawler_state.read().unwrap().enr_records, self.output_format, &self.datadir, &output_name, &log);
                         },
                         _ => ()
                     }
                 },
                 _ = query_interval.next() => {
-                    let mut index = 1;
-                    let timestamp = format!("{}", Local::now().format("%Y-%m-%d][%H:%M:%S"));
+                    let timestamp = format!("{}", Local::now().format(TIMESTAMP_FORMAT));
                     // pick a random node target
                     let target_random_node_id = NodeId::random();
 
@@ -392,7 +771,8 @@ impl Crawler {
                         info!(log,"calling find_node()");
                         discv5.find_node(target_random_node_id).await
                     } else if enr_added_count % 2 == 0 {
-                        let node_ids_discovered: Vec<String> = enr_records.keys().cloned().collect();
+                        let node_ids_discovered: Vec<String> =
+                            crawler_state.read().unwrap().enr_records.keys().cloned().collect();
                         let x = target_fork_digest.clone();
                         // predicate for finding nodes with a matching fork_digest
                         let eth2_fork_predicate =
@@ -418,69 +798,167 @@ impl Crawler {
                         info!(log,"calling find_node()");
                         discv5.find_node(target_random_node_id).await
                     };
+                    // discv5 resolves a timed out query with whatever it found so far
+                    let query_result = match &enrs {
+                        Ok(enrs) if !enrs.is_empty() => "success",
+                        Ok(_) => "empty",
+                        Err(_) => "error",
+                    };
+                    metrics::CRAWLER_QUERIES.with_label_values(&[query_result]).inc();
 
-                    for enr in discv5.table_entries_enr() {
-                        let enr_entry = EnrEntry::new(&enr);
-                        if target_enr == "".to_string()
-                        && (enr_entry.fork_digest == target_fork_digest || target_fork_digest.is_empty())
-                        {
-                            target_enr = enr.to_base64();
+                    let table_entries = discv5.table_entries_enr();
+                    enr_added_count += table_entries.len() as u64;
+                    let updated = crawler_state.write().unwrap().update(&table_entries, &timestamp);
+                    if let Some(feed) = self.feed.as_ref().filter(|feed| feed.is_subscribed()) {
+                        for enr_record in &updated {
+                            if let Ok(value) = merge_json(enr_record, &enr_record.enr) {
+                                feed.publish_value(ENR_EVENT, value);
+                            }
                         }
-                        let enr_record = enr_records.entry(enr_entry.node_id.clone()).or_default(); //None if new
-                        *enr_record = EnrRecord::new(index, timestamp.clone(), enr_entry);
-                        index += 1;
-                        enr_added_count += 1;
                     }
-                    info!(log, "Connected Peers: {}", discv5.connected_peers());
-                    info!(log, "Enr Entries: {:?}", enr_records.len());
-                    if target_enr != "".to_string() {
-                        let fork_id = get_fork_id_from_enr(
-                            &target_enr.parse::<Enr<CombinedKey>>().unwrap(),
-                        );
-                        match fork_id {
-                            Some(x) => {
-                                //info!(log,"rnum:{}",rnum);
-                                if target_fork_digest.is_empty() || target_fork_digest == hex::encode(&x.fork_digest)
-                                {
-                                    let num_on_fork = enr_records.values().filter(|enr_record| {
-                                        match enr_record.get_enr() {
-                                            Some(y) => {
-                                                let enr_record_fork_digest = match get_fork_id_from_enr(&y) {
-                                                    Some(fork_id) => hex::encode(&fork_id.fork_digest),
-                                                    _ => "".to_string(),
-                                                };
-                                                let fork_id_match = hex::encode(&x.fork_digest) == enr_record_fork_digest;
-                                                //info!(log,"fork_digest:{} {:?}",enr_record_fork_digest,fork_id_match);
-                                                fork_id_match
-                                            },
-                                            _ => false
-                                        }
-                                    }).count();
-                                    info!(log, "Enr Entries on correct fork_digest: {:?}", num_on_fork);
-                                }
+                    if let Some(sink) = stream_sink.as_mut() {
+                        for enr_record in &updated {
+                            if let Err(e) = sink.write_pair(enr_record, &enr_record.enr) {
+                                warn!(log, "Failed to write record: {}", e);
                             }
-                            _ => ()
                         }
                     }
+                    let (num_records, fork_stats, target_count) = {
+                        let mut state = crawler_state.write().unwrap();
+                        state.stats.query_rounds += 1;
+                        state.stats.connected_peers = discv5.connected_peers();
+                        (state.enr_records.len(), state.fork_index.stats(), state.fork_index.count(&target_fork_digest))
+                    };
+                    info!(log, "Connected Peers: {}", discv5.connected_peers());
+                    info!(log, "Enr Entries: {:?}", num_records);
+                    metrics::CRAWLER_CONNECTED_PEERS.set(discv5.connected_peers() as i64);
+                    metrics::CRAWLER_NODES.reset();
+                    for fork_stat in fork_stats {
+                        metrics::CRAWLER_NODES
+                            .with_label_values(&[&fork_stat.fork_digest, &fork_stat.network])
+                            .set(fork_stat.nodes as i64);
+                        info!(
+                            log,
+                            "Enr Entries on fork_digest {:?} ({}): {:?}",
+                            fork_stat.fork_digest,
+                            fork_stat.network,
+                            fork_stat.nodes
+                        );
+                    }
+                    if !target_fork_digest.is_empty() {
+                        info!(
+                            log,
+                            "Enr Entries on target fork_digest: {:?}",
+                            target_count
+                        );
+                    }
+
+                    idle_rounds = match num_records - num_records_prev {
+                        0 => idle_rounds + 1,
+                        _ => 0,
+                    };
+                    num_records_prev = num_records;
+                    if limits.max_nodes.map_or(false, |max| num_records >= max) {
+                        info!(log, "Discovered {} nodes.", num_records);
+                        break StopReason::MaxNodes;
+                    }
+                    if limits.saturation_rounds.map_or(false, |max| idle_rounds >= max) {
+                        info!(log, "No new nodes found in {} query rounds.", idle_rounds);
+                        break StopReason::Saturated;
+                    }
                 }
             }
+        };
+
+        match self.output_mode.as_str() {
+            "snapshot" => {
+                info!(log,"Output is enabled.  Saving data to file");
+                Crawler::write_snapshot(&crawler_state.read().unwrap().enr_records, self.output_format, &self.datadir, &output_name, &log);
+            },
+            _ => info!(log,"Output is disabled.  Not saving to file.")
+        };
+        if let Some(sink) = stream_sink.as_mut() {
+            let _ = sink.flush();
+        }
+
+        let summary = {
+            let state = crawler_state.read().unwrap();
+            CrawlSummary {
+                stop_reason,
+                elapsed_secs: started_at.elapsed().as_secs(),
+                stats: state.stats(),
+                forks: state.forks(),
+            }
+        };
+        info!(
+            log,
+            "Crawl stopped";
+            "reason" => format!("{:?}", summary.stop_reason),
+            "elapsed_secs" => summary.elapsed_secs,
+            "query_rounds" => summary.stats.query_rounds,
+            "nodes" => summary.stats.nodes,
+            "fork_digests" => summary.stats.fork_digests
+        );
+        if self.output_mode != "none" {
+            let summary_path = self.datadir.join(format!("{}-summary.json", output_name));
+            let result = std::fs::create_dir_all(&self.datadir)
+                .and_then(|_| File::create(&summary_path))
+                .and_then(|file| {
+                    serde_json::to_writer_pretty(file, &summary).map_err(io::Error::from)
+                });
+            if let Err(e) = result {
+                warn!(log, "Failed to write crawl summary: {}", e);
+            }
         }
+        summary
     }
 
-    fn write_file(records: &HashMap<String, EnrRecord>, path: PathBuf) {
-        let file = OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .create(true)
-            .append(false)
-            .open(path)
-            .unwrap();
-        let mut wtr = csv::Writer::from_writer(file);
-
+    fn write_snapshot(
+        records: &HashMap<String, EnrRecord>,
+        output_format: OutputFormat,
+        datadir: &PathBuf,
+        name: &str,
+        log: &slog::Logger,
+    ) {
+        if output_format.is_streaming() {
+            return;
+        }
+        if output_format == OutputFormat::Parquet {
+            // each snapshot replaces the previous file, so it is never rolled over
+            let result = ParquetSink::create(datadir, name, Rotation::default()).and_then(|mut sink| {
+                for enr_record in records.values() {
+                    sink.write(enr_record.clone())?;
+                }
+                sink.close()
+            });
+            if let Err(e) = result {
+                warn!(log, "Failed to write parquet snapshot: {}", e);
+            }
+            return;
+        }
+        let mut sink = match Sink::create(output_format, datadir, name, true) {
+            Ok(sink) => sink,
+            Err(e) => {
+                warn!(log, "Failed to open output file: {}", e);
+                return;
+            }
+        };
         for enr_record in records.values() {
-            let _ = wtr.serialize((&enr_record, &enr_record.enr));
-            let _ = wtr.flush();
+            if let Err(e) = sink.write_pair(enr_record, &enr_record.enr) {
+                warn!(log, "Failed to write record: {}", e);
+            }
         }
+        if let Err(e) = sink.flush() {
+            warn!(log, "Failed to flush output file: {}", e);
+        }
+    }
+}
+
+/// Accepts whole numbers of at least 1.
+fn is_positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(x) if x > 0 => Ok(()),
+        _ => Err(format!("expected a whole number of at least 1, got {:?}", value)),
     }
 }
 
@@ -530,6 +1008,36 @@ pub fn cli_app<'a, 'b>() -> App<'a, 'b> {
             .default_value("")
             .takes_value(true),
     )
+    .arg(
+        Arg::with_name("query-interval")
+            .long("query-interval")
+            .value_name("SECONDS")
+            .help("Time between discv5 query rounds.")
+            .default_value("10")
+            .takes_value(true)
+            .validator(is_positive),
+    )
+    .arg(
+        Arg::with_name("duration")
+            .long("duration")
+            .value_name("SECONDS")
+            .help("Stop crawling after this many seconds.")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("max-nodes")
+            .long("max-nodes")
+            .value_name("N")
+            .help("Stop crawling once this many unique nodes have been discovered.")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("saturation-rounds")
+            .long("saturation-rounds")
+            .value_name("K")
+            .help("Stop crawling after K consecutive query rounds without a new node.")
+            .takes_value(true),
+    )
     .arg(
         Arg::with_name("boot-nodes")
             .long("boot-nodes")
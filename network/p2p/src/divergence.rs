@@ -0,0 +1,101 @@
+use crate::metrics;
+use crate::peers::PeerRecord;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// Fewest peers a finalized root needs before its group counts as a side of
+/// a split.
+const MIN_GROUP_PEERS: u64 = 2;
+/// Smallest share of the peers reporting an epoch a group needs to count.
+const MIN_GROUP_SHARE: f64 = 0.1;
+
+/// Peers whose latest Status names the same finalized checkpoint.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CheckpointGroup {
+    pub fork_digest: String,
+    pub finalized_epoch: u64,
+    pub finalized_root: String,
+    pub peers: u64,
+    /// Peers per client, taken from the agent string.
+    pub clients: BTreeMap<String, u64>,
+}
+
+/// Significant groups of peers that finalized different roots for the same
+/// epoch, a sign of a chain split.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub fork_digest: String,
+    pub finalized_epoch: u64,
+    pub groups: Vec<CheckpointGroup>,
+}
+
+/// Groups peers by fork digest and finalized checkpoint, largest group of
+/// each epoch first.
+pub fn checkpoint_groups<'a, I>(peers: I) -> Vec<CheckpointGroup>
+where
+    I: IntoIterator<Item = &'a PeerRecord>,
+{
+    let mut groups: BTreeMap<(String, u64, String), CheckpointGroup> = BTreeMap::new();
+    for peer in peers {
+        let (fork_digest, finalized_epoch, finalized_root) =
+            match (&peer.fork_digest, peer.finalized_epoch, &peer.finalized_root) {
+                (Some(fork_digest), Some(epoch), Some(root)) => (fork_digest, epoch, root),
+                _ => continue,
+            };
+        let group = groups
+            .entry((fork_digest.clone(), finalized_epoch, finalized_root.clone()))
+            .or_insert_with(|| CheckpointGroup {
+                fork_digest: fork_digest.clone(),
+                finalized_epoch,
+                finalized_root: finalized_root.clone(),
+                peers: 0,
+                clients: BTreeMap::new(),
+            });
+        group.peers += 1;
+        *group
+            .clients
+            .entry(metrics::agent_client(&peer.agent_string).to_string())
+            .or_default() += 1;
+    }
+    let mut groups: Vec<CheckpointGroup> = groups.into_iter().map(|(_, group)| group).collect();
+    groups.sort_by(|a, b| {
+        (&a.fork_digest, a.finalized_epoch)
+            .cmp(&(&b.fork_digest, b.finalized_epoch))
+            .then(b.peers.cmp(&a.peers))
+    });
+    groups
+}
+
+/// Returns the epochs at which at least two significant groups disagree on
+/// the finalized root.
+pub fn divergences(groups: &[CheckpointGroup]) -> Vec<Divergence> {
+    let mut by_epoch: BTreeMap<(&str, u64), Vec<&CheckpointGroup>> = BTreeMap::new();
+    for group in groups {
+        by_epoch
+            .entry((&group.fork_digest, group.finalized_epoch))
+            .or_default()
+            .push(group);
+    }
+    by_epoch
+        .into_iter()
+        .filter_map(|((fork_digest, finalized_epoch), groups)| {
+            let total: u64 = groups.iter().map(|group| group.peers).sum();
+            let significant: Vec<CheckpointGroup> = groups
+                .into_iter()
+                .filter(|group| {
+                    group.peers >= MIN_GROUP_PEERS
+                        && group.peers as f64 >= total as f64 * MIN_GROUP_SHARE
+                })
+                .cloned()
+                .collect();
+            if significant.len() < 2 {
+                return None;
+            }
+            Some(Divergence {
+                fork_digest: fork_digest.to_string(),
+                finalized_epoch,
+                groups: significant,
+            })
+        })
+        .collect()
+}
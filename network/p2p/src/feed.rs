@@ -0,0 +1,90 @@
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use serde::Serialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before slow subscribers start missing events.
+const FEED_CAPACITY: usize = 4096;
+
+pub const ENR_EVENT: &str = "enr";
+pub const GOSSIP_EVENT: &str = "gossip";
+pub const BLOCK_EVENT: &str = "block";
+
+/// A live event pushed to feed subscribers.
+#[derive(Clone, Debug)]
+pub struct FeedEvent {
+    pub kind: &'static str,
+    pub data: serde_json::Value,
+}
+
+impl FeedEvent {
+    /// Formats the event as a server-sent event.
+    pub fn to_sse(&self) -> String {
+        format!("event: {}\ndata: {}\n\n", self.kind, self.data)
+    }
+}
+
+/// Publishes crawler discoveries and gossip records to live subscribers.
+#[derive(Clone)]
+pub struct Feed {
+    sender: broadcast::Sender<FeedEvent>,
+}
+
+impl Feed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        Feed { sender }
+    }
+
+    /// Serializes and publishes `data`, skipping the work when nobody is subscribed.
+    pub fn publish<T: Serialize>(&self, kind: &'static str, data: &T) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        if let Ok(data) = serde_json::to_value(data) {
+            let _ = self.sender.send(FeedEvent { kind, data });
+        }
+    }
+
+    pub fn publish_value(&self, kind: &'static str, data: serde_json::Value) {
+        let _ = self.sender.send(FeedEvent { kind, data });
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Returns a stream of server-sent events limited to `kinds`, or all
+    /// events when `kinds` is empty.
+    pub fn sse_stream(
+        &self,
+        kinds: Vec<String>,
+    ) -> impl Stream<Item = Result<String, Infallible>> + Send + 'static {
+        self.sender
+            .subscribe()
+            .take_while(|event| {
+                future::ready(match event {
+                    Err(broadcast::RecvError::Closed) => false,
+                    _ => true,
+                })
+            })
+            .filter_map(move |event| {
+                let sse = match event {
+                    Ok(event) if kinds.is_empty() || kinds.iter().any(|x| x == event.kind) => {
+                        Some(Ok(event.to_sse()))
+                    }
+                    // tell the subscriber it missed events instead of closing the stream
+                    Err(broadcast::RecvError::Lagged(n)) => Some(Ok(format!(": lagged {}\n\n", n))),
+                    _ => None,
+                };
+                future::ready(sse)
+            })
+    }
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Feed::new()
+    }
+}
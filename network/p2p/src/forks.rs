@@ -0,0 +1,81 @@
+use eth2::testnet::presets::get_network_name;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+/// Node count for a single fork digest seen in the DHT.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ForkStat {
+    pub fork_digest: String,
+    pub network: String,
+    pub nodes: usize,
+}
+
+/// Running index of discovered nodes keyed by fork digest.
+///
+/// Nodes are moved between digests when a newer ENR advertises a different
+/// fork, so the counts always reflect the latest record seen for each node.
+#[derive(Default)]
+pub struct ForkIndex {
+    node_forks: HashMap<String, String>,
+    counts: HashMap<String, usize>,
+}
+
+impl ForkIndex {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, node_id: &str, fork_digest: &str) {
+        match self.node_forks.get(node_id) {
+            Some(previous) if previous == fork_digest => return,
+            Some(previous) => {
+                if let Some(count) = self.counts.get_mut(previous) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.counts.remove(previous);
+                    }
+                }
+            }
+            None => (),
+        }
+        self.node_forks
+            .insert(node_id.to_string(), fork_digest.to_string());
+        *self.counts.entry(fork_digest.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn count(&self, fork_digest: &str) -> usize {
+        self.counts.get(fork_digest).cloned().unwrap_or(0)
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.node_forks.len()
+    }
+
+    /// Returns the node count of every fork digest, largest first.
+    pub fn stats(&self) -> Vec<ForkStat> {
+        let mut stats: Vec<ForkStat> = self
+            .counts
+            .iter()
+            .map(|(fork_digest, nodes)| ForkStat {
+                fork_digest: fork_digest.clone(),
+                network: network_name(fork_digest),
+                nodes: *nodes,
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            b.nodes
+                .cmp(&a.nodes)
+                .then_with(|| a.fork_digest.cmp(&b.fork_digest))
+        });
+        stats
+    }
+}
+
+pub fn network_name(fork_digest: &str) -> String {
+    match fork_digest {
+        "" => "none".to_string(),
+        _ => get_network_name(fork_digest)
+            .unwrap_or("unknown")
+            .to_string(),
+    }
+}
@@ -0,0 +1,455 @@
+use crate::columnar::{parse_unix_millis, ParquetRecord};
+use crate::metrics;
+use arrow::array::{
+    ArrayRef, BooleanBuilder, Int64Builder, ListBuilder, StringBuilder, TimestampMillisecondBuilder, UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::Result as ArrowResult;
+use arrow::record_batch::RecordBatch;
+use eth2::ssz::Decode;
+use eth2::types::{
+    Attestation, AttesterSlashing, EthSpec, Hash256, MainnetEthSpec, ProposerSlashing, SignedAggregateAndProof,
+    SignedBeaconBlock, SignedVoluntaryExit,
+};
+use serde_derive::Serialize;
+use snap::raw::{decompress_len, Decoder};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// Largest decompressed gossip payload accepted, as in the p2p spec.
+const GOSSIP_MAX_SIZE: usize = 1_048_576;
+
+const ATTESTATION_SUBNET_PREFIX: &str = "beacon_attestation_";
+
+/// A decoded gossip message.
+///
+/// Every topic fills in the common fields. `subnet_id`, `committee_index`,
+/// the attestation fields and `validator_indices` are only set by the topics
+/// that carry them.
+#[derive(Serialize, Default, Clone)]
+pub struct GossipRecord {
+    pub index: u64,
+    pub timestamp: String,
+    pub message_id: String,
+    /// Whether `message_id` matches the spec computation over the payload.
+    pub message_id_valid: bool,
+    pub sequence_number: u64,
+    pub agent_string: String,
+    pub peer_id: String,
+    pub topic: String,
+    pub message_size: usize,
+    pub slot: u64,
+    /// Milliseconds between the start of `slot` and the arrival of the
+    /// message, or `None` when the genesis time is unknown.
+    pub slot_offset_ms: Option<i64>,
+    pub validator_index: Option<u64>,
+    pub subnet_id: Option<u64>,
+    pub committee_index: Option<u64>,
+    /// Set bits of the aggregation bits of an aggregate or attestation.
+    pub attesters: Option<u64>,
+    /// Length of the aggregation bits, the size of the committee.
+    pub committee_size: Option<u64>,
+    pub source_epoch: Option<u64>,
+    pub target_epoch: Option<u64>,
+    pub target_root: Option<String>,
+    /// `validators` joined with `;`, for the row formats.
+    pub validator_indices: String,
+    /// Peer that delivered this message id first.
+    pub first_peer_id: String,
+    /// Deliveries of this message id so far, including this one.
+    pub deliveries: u64,
+    /// Milliseconds between the first delivery and this one.
+    pub spread_ms: u64,
+    #[serde(skip_serializing)]
+    pub validators: Vec<u64>,
+}
+
+/// A decoded gossip payload.
+pub enum GossipMessage {
+    Block(Box<SignedBeaconBlock<MainnetEthSpec>>),
+    Aggregate(Box<SignedAggregateAndProof<MainnetEthSpec>>),
+    Attestation(u64, Box<Attestation<MainnetEthSpec>>),
+    VoluntaryExit(Box<SignedVoluntaryExit>),
+    ProposerSlashing(Box<ProposerSlashing>),
+    AttesterSlashing(Box<AttesterSlashing<MainnetEthSpec>>),
+}
+
+/// Topic specific fields of a decoded message.
+#[derive(Default)]
+struct Decoded {
+    slot: u64,
+    validator_index: Option<u64>,
+    subnet_id: Option<u64>,
+    committee_index: Option<u64>,
+    aggregation_bits: Vec<bool>,
+    source_epoch: Option<u64>,
+    target: Option<(u64, Hash256)>,
+    validators: Vec<u64>,
+}
+
+impl GossipRecord {
+    pub fn new(
+        index: u64,
+        timestamp: String,
+        message_id: String,
+        sequence_number: u64,
+        agent_string: String,
+        peer_id: String,
+        topic: String,
+        data: &[u8],
+    ) -> Result<(Self, GossipMessage), String> {
+        let message = decode(metrics::topic_kind(&topic), data)?;
+        let decoded = message.fields();
+        let (attesters, committee_size) = if decoded.aggregation_bits.is_empty() {
+            (None, None)
+        } else {
+            let bits = &decoded.aggregation_bits;
+            (
+                Some(bits.iter().filter(|bit| **bit).count() as u64),
+                Some(bits.len() as u64),
+            )
+        };
+        let record = GossipRecord {
+            index,
+            timestamp,
+            message_id,
+            message_id_valid: true,
+            sequence_number,
+            agent_string,
+            peer_id,
+            topic,
+            message_size: data.len(),
+            slot: decoded.slot,
+            slot_offset_ms: None,
+            validator_index: decoded.validator_index,
+            subnet_id: decoded.subnet_id,
+            committee_index: decoded.committee_index,
+            attesters,
+            committee_size,
+            source_epoch: decoded.source_epoch,
+            target_epoch: decoded.target.map(|(epoch, _)| epoch),
+            target_root: decoded.target.map(|(_, root)| format!("{:?}", root)),
+            validator_indices: decoded
+                .validators
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(";"),
+            first_peer_id: String::new(),
+            deliveries: 0,
+            spread_ms: 0,
+            validators: decoded.validators,
+        };
+        Ok((record, message))
+    }
+}
+
+/// Returns the arrival time `arrival_ms` relative to the start of `slot`, or
+/// `None` if the slot is too far in the future to have a start time.
+pub fn slot_offset_ms(
+    genesis_time: u64,
+    milliseconds_per_slot: u64,
+    slot: u64,
+    arrival_ms: u64,
+) -> Option<i64> {
+    let slot_start_ms = genesis_time
+        .checked_mul(1000)?
+        .checked_add(slot.checked_mul(milliseconds_per_slot)?)?;
+    let slot_start_ms = i64::try_from(slot_start_ms).ok()?;
+    i64::try_from(arrival_ms).ok()?.checked_sub(slot_start_ms)
+}
+
+/// Number of decoded records kept for duplicate deliveries.
+const RECENT_RECORDS_CAPACITY: usize = 16384;
+
+/// Decoded records of recent message ids, so duplicate deliveries can be
+/// recorded without decoding them again. The oldest records are evicted
+/// first.
+#[derive(Default)]
+pub struct RecentRecords {
+    records: HashMap<String, GossipRecord>,
+    order: VecDeque<String>,
+}
+
+impl RecentRecords {
+    pub fn get(&self, message_id: &str) -> Option<&GossipRecord> {
+        self.records.get(message_id)
+    }
+
+    /// Keeps `record` for its message id unless one is kept already.
+    pub fn insert(&mut self, record: GossipRecord) {
+        if self.records.contains_key(&record.message_id) {
+            return;
+        }
+        if self.order.len() >= RECENT_RECORDS_CAPACITY {
+            if let Some(evicted) = self.order.pop_front() {
+                self.records.remove(&evicted);
+            }
+        }
+        self.order.push_back(record.message_id.clone());
+        self.records.insert(record.message_id.clone(), record);
+    }
+}
+
+/// Returns true for topics `GossipRecord::new` knows how to decode.
+pub fn is_supported_topic(topic: &str) -> bool {
+    match metrics::topic_kind(topic) {
+        "beacon_block" | "beacon_aggregate_and_proof" | "voluntary_exit" | "proposer_slashing"
+        | "attester_slashing" => true,
+        kind => attestation_subnet(kind).is_some(),
+    }
+}
+
+/// Returns the subnet id of a `beacon_attestation_{subnet_id}` topic.
+fn attestation_subnet(kind: &str) -> Option<u64> {
+    if kind.starts_with(ATTESTATION_SUBNET_PREFIX) {
+        kind[ATTESTATION_SUBNET_PREFIX.len()..].parse::<u64>().ok()
+    } else {
+        None
+    }
+}
+
+/// Decompresses an `ssz_snappy` gossip payload.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decompressed_data: Vec<u8> = Vec::new();
+    match decompress_len(data) {
+        Ok(n) if n > GOSSIP_MAX_SIZE => {
+            return Err("ssz_snappy decoded data > GOSSIP_MAX_SIZE".into());
+        }
+        Ok(n) => decompressed_data.resize(n, 0),
+        Err(e) => {
+            return Err(format!("{}", e));
+        }
+    };
+    let mut decoder = Decoder::new();
+    match decoder.decompress(data, &mut decompressed_data) {
+        Ok(n) => {
+            decompressed_data.truncate(n);
+            Ok(decompressed_data)
+        }
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+fn decode(kind: &str, data: &[u8]) -> Result<GossipMessage, String> {
+    let error = |e| format!("{:#?}", e);
+    let message = match kind {
+        "beacon_block" => GossipMessage::Block(Box::new(
+            SignedBeaconBlock::from_ssz_bytes(data).map_err(error)?,
+        )),
+        "beacon_aggregate_and_proof" => GossipMessage::Aggregate(Box::new(
+            SignedAggregateAndProof::from_ssz_bytes(data).map_err(error)?,
+        )),
+        "voluntary_exit" => GossipMessage::VoluntaryExit(Box::new(
+            SignedVoluntaryExit::from_ssz_bytes(data).map_err(error)?,
+        )),
+        "proposer_slashing" => GossipMessage::ProposerSlashing(Box::new(
+            ProposerSlashing::from_ssz_bytes(data).map_err(error)?,
+        )),
+        "attester_slashing" => GossipMessage::AttesterSlashing(Box::new(
+            AttesterSlashing::from_ssz_bytes(data).map_err(error)?,
+        )),
+        kind => match attestation_subnet(kind) {
+            Some(subnet_id) => GossipMessage::Attestation(
+                subnet_id,
+                Box::new(Attestation::from_ssz_bytes(data).map_err(error)?),
+            ),
+            None => return Err(format!("unsupported gossip topic: {}", kind)),
+        },
+    };
+    Ok(message)
+}
+
+/// Fields shared by aggregates and single attestations.
+fn attestation_fields(attestation: &Attestation<MainnetEthSpec>) -> Decoded {
+    let data = &attestation.data;
+    Decoded {
+        slot: data.slot.into(),
+        committee_index: Some(data.index),
+        aggregation_bits: attestation.aggregation_bits.iter().collect(),
+        source_epoch: Some(data.source.epoch.into()),
+        target: Some((data.target.epoch.into(), data.target.root)),
+        ..Default::default()
+    }
+}
+
+impl GossipMessage {
+    fn fields(&self) -> Decoded {
+        match self {
+            GossipMessage::Block(block) => Decoded {
+                slot: block.message.slot.into(),
+                validator_index: Some(block.message.proposer_index),
+                ..Default::default()
+            },
+            GossipMessage::Aggregate(aggregate) => Decoded {
+                validator_index: Some(aggregate.message.aggregator_index),
+                ..attestation_fields(&aggregate.message.aggregate)
+            },
+            GossipMessage::Attestation(subnet_id, attestation) => Decoded {
+                subnet_id: Some(*subnet_id),
+                ..attestation_fields(attestation)
+            },
+            GossipMessage::VoluntaryExit(exit) => Decoded {
+                slot: exit
+                    .message
+                    .epoch
+                    .start_slot(MainnetEthSpec::slots_per_epoch())
+                    .into(),
+                validator_index: Some(exit.message.validator_index),
+                ..Default::default()
+            },
+            GossipMessage::ProposerSlashing(slashing) => {
+                let header = &slashing.signed_header_1.message;
+                Decoded {
+                    slot: header.slot.into(),
+                    validator_index: Some(header.proposer_index),
+                    ..Default::default()
+                }
+            }
+            GossipMessage::AttesterSlashing(slashing) => {
+                let data_1 = &slashing.attestation_1.data;
+                // only validators that signed both attestations are slashable
+                let signed_2: HashSet<_> =
+                    slashing.attestation_2.attesting_indices.iter().collect();
+                let validators = slashing
+                    .attestation_1
+                    .attesting_indices
+                    .iter()
+                    .filter(|x| signed_2.contains(x))
+                    .cloned()
+                    .collect();
+                Decoded {
+                    slot: data_1.slot.into(),
+                    committee_index: Some(data_1.index),
+                    validators,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+impl ParquetRecord for GossipRecord {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("index", DataType::UInt64, false),
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("message_id", DataType::Utf8, false),
+            Field::new("message_id_valid", DataType::Boolean, false),
+            Field::new("sequence_number", DataType::UInt64, false),
+            Field::new("agent_string", DataType::Utf8, false),
+            Field::new("peer_id", DataType::Utf8, false),
+            Field::new("topic", DataType::Utf8, false),
+            Field::new("message_size", DataType::UInt64, false),
+            Field::new("slot", DataType::UInt64, false),
+            Field::new("slot_offset_ms", DataType::Int64, true),
+            Field::new("validator_index", DataType::UInt64, true),
+            Field::new("subnet_id", DataType::UInt64, true),
+            Field::new("committee_index", DataType::UInt64, true),
+            Field::new("attesters", DataType::UInt64, true),
+            Field::new("committee_size", DataType::UInt64, true),
+            Field::new("source_epoch", DataType::UInt64, true),
+            Field::new("target_epoch", DataType::UInt64, true),
+            Field::new("target_root", DataType::Utf8, true),
+            Field::new(
+                "validator_indices",
+                DataType::List(Box::new(Field::new("item", DataType::UInt64, true))),
+                false,
+            ),
+            Field::new("first_peer_id", DataType::Utf8, false),
+            Field::new("deliveries", DataType::UInt64, false),
+            Field::new("spread_ms", DataType::UInt64, false),
+        ]))
+    }
+
+    fn to_record_batch(records: &[Self]) -> ArrowResult<RecordBatch> {
+        let n = records.len();
+        let mut index = UInt64Builder::new(n);
+        let mut timestamp = TimestampMillisecondBuilder::new(n);
+        let mut message_id = StringBuilder::new(n);
+        let mut message_id_valid = BooleanBuilder::new(n);
+        let mut sequence_number = UInt64Builder::new(n);
+        let mut agent_string = StringBuilder::new(n);
+        let mut peer_id = StringBuilder::new(n);
+        let mut topic = StringBuilder::new(n);
+        let mut message_size = UInt64Builder::new(n);
+        let mut slot = UInt64Builder::new(n);
+        let mut slot_offset_ms = Int64Builder::new(n);
+        let mut validator_index = UInt64Builder::new(n);
+        let mut subnet_id = UInt64Builder::new(n);
+        let mut committee_index = UInt64Builder::new(n);
+        let mut attesters = UInt64Builder::new(n);
+        let mut committee_size = UInt64Builder::new(n);
+        let mut source_epoch = UInt64Builder::new(n);
+        let mut target_epoch = UInt64Builder::new(n);
+        let mut target_root = StringBuilder::new(n);
+        let mut validator_indices = ListBuilder::new(UInt64Builder::new(n));
+        let mut first_peer_id = StringBuilder::new(n);
+        let mut deliveries = UInt64Builder::new(n);
+        let mut spread_ms = UInt64Builder::new(n);
+        for record in records {
+            index.append_value(record.index)?;
+            timestamp.append_option(parse_unix_millis(&record.timestamp))?;
+            message_id.append_value(&record.message_id)?;
+            message_id_valid.append_value(record.message_id_valid)?;
+            sequence_number.append_value(record.sequence_number)?;
+            agent_string.append_value(&record.agent_string)?;
+            peer_id.append_value(&record.peer_id)?;
+            topic.append_value(&record.topic)?;
+            message_size.append_value(record.message_size as u64)?;
+            slot.append_value(record.slot)?;
+            slot_offset_ms.append_option(record.slot_offset_ms)?;
+            validator_index.append_option(record.validator_index)?;
+            subnet_id.append_option(record.subnet_id)?;
+            committee_index.append_option(record.committee_index)?;
+            attesters.append_option(record.attesters)?;
+            committee_size.append_option(record.committee_size)?;
+            source_epoch.append_option(record.source_epoch)?;
+            target_epoch.append_option(record.target_epoch)?;
+            match &record.target_root {
+                Some(root) => target_root.append_value(root)?,
+                None => target_root.append_null()?,
+            }
+            for validator in &record.validators {
+                validator_indices.values().append_value(*validator)?;
+            }
+            validator_indices.append(true)?;
+            first_peer_id.append_value(&record.first_peer_id)?;
+            deliveries.append_value(record.deliveries)?;
+            spread_ms.append_value(record.spread_ms)?;
+        }
+        RecordBatch::try_new(
+            Self::schema(),
+            vec![
+                Arc::new(index.finish()) as ArrayRef,
+                Arc::new(timestamp.finish()),
+                Arc::new(message_id.finish()),
+                Arc::new(message_id_valid.finish()),
+                Arc::new(sequence_number.finish()),
+                Arc::new(agent_string.finish()),
+                Arc::new(peer_id.finish()),
+                Arc::new(topic.finish()),
+                Arc::new(message_size.finish()),
+                Arc::new(slot.finish()),
+                Arc::new(slot_offset_ms.finish()),
+                Arc::new(validator_index.finish()),
+                Arc::new(subnet_id.finish()),
+                Arc::new(committee_index.finish()),
+                Arc::new(attesters.finish()),
+                Arc::new(committee_size.finish()),
+                Arc::new(source_epoch.finish()),
+                Arc::new(target_epoch.finish()),
+                Arc::new(target_root.finish()),
+                Arc::new(validator_indices.finish()),
+                Arc::new(first_peer_id.finish()),
+                Arc::new(deliveries.finish()),
+                Arc::new(spread_ms.finish()),
+            ],
+        )
+    }
+}
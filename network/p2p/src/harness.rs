@@ -0,0 +1,172 @@
+//! In-process discv5 network used by the crawler integration tests.
+//!
+//! Every node listens on 127.0.0.1 inside the caller's tokio runtime and
+//! advertises a synthetic `eth2` fork id and `attnets` bitfield.
+use crate::crawler;
+use crate::replay;
+use clap::{App, Arg, ArgMatches};
+use discv5::{
+    enr::{CombinedKey, Enr, EnrBuilder},
+    Discv5, Discv5ConfigBuilder,
+};
+use eth2::libp2p::types::EnrBitfield;
+use eth2::ssz::Encode;
+use eth2::types::MainnetEthSpec;
+use eth2::utils::get_fork_id;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Describes the records advertised by one test node.
+#[derive(Clone, Debug)]
+pub struct NodeSpec {
+    pub fork_digest: Option<[u8; 4]>,
+    pub attnets: Vec<u64>,
+}
+
+impl NodeSpec {
+    pub fn new(fork_digest: [u8; 4], attnets: Vec<u64>) -> Self {
+        NodeSpec {
+            fork_digest: Some(fork_digest),
+            attnets,
+        }
+    }
+
+    /// A node without an `eth2` entry, like non eth2 discv5 peers.
+    pub fn without_fork() -> Self {
+        NodeSpec {
+            fork_digest: None,
+            attnets: vec![],
+        }
+    }
+}
+
+pub struct TestNode {
+    pub spec: NodeSpec,
+    pub enr: Enr<CombinedKey>,
+    // kept alive so the node keeps answering queries
+    discv5: Discv5,
+}
+
+/// A set of discv5 nodes that know about each other in a ring, so a crawler
+/// bootstrapped from any single node has to walk the DHT to find the rest.
+pub struct TestNetwork {
+    pub nodes: Vec<TestNode>,
+}
+
+impl TestNetwork {
+    /// Starts one node per spec on a free port.
+    /// Must be called from within a tokio runtime.
+    pub fn start(specs: &[NodeSpec]) -> Self {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut nodes: Vec<TestNode> = specs
+            .iter()
+            .map(|spec| {
+                let port = free_port();
+                let enr_key = CombinedKey::generate_secp256k1();
+                let enr = build_enr(spec, ip, port, &enr_key);
+                let config = Discv5ConfigBuilder::new()
+                    .request_timeout(Duration::from_secs(1))
+                    .query_timeout(Duration::from_secs(5))
+                    .build();
+                let mut discv5 = Discv5::new(enr.clone(), enr_key, config).unwrap();
+                discv5.start(SocketAddr::new(ip, port));
+                TestNode {
+                    spec: spec.clone(),
+                    enr,
+                    discv5,
+                }
+            })
+            .collect();
+
+        let num_nodes = nodes.len();
+        let enrs: Vec<Enr<CombinedKey>> = nodes.iter().map(|x| x.enr.clone()).collect();
+        for (i, node) in nodes.iter_mut().enumerate() {
+            for offset in 1..=2 {
+                if num_nodes > offset {
+                    let _ = node.discv5.add_enr(enrs[(i + offset) % num_nodes].clone());
+                }
+            }
+        }
+        TestNetwork { nodes }
+    }
+
+    pub fn boot_enr(&self) -> String {
+        self.nodes[0].enr.to_base64()
+    }
+
+    /// Number of nodes advertising `fork_digest`, or no fork when `None`.
+    pub fn count(&self, fork_digest: Option<[u8; 4]>) -> usize {
+        self.nodes
+            .iter()
+            .filter(|x| x.spec.fork_digest == fork_digest)
+            .count()
+    }
+}
+
+fn build_enr(spec: &NodeSpec, ip: IpAddr, port: u16, enr_key: &CombinedKey) -> Enr<CombinedKey> {
+    let mut builder = EnrBuilder::new("v4");
+    builder.ip(ip).udp(port);
+    if let Some(fork_digest) = spec.fork_digest {
+        let fork_id = get_fork_id(fork_digest.to_vec(), vec![0, 0, 0, 0], u64::max_value());
+        builder.add_value("eth2".into(), fork_id.as_ssz_bytes());
+        let mut attnets = EnrBitfield::<MainnetEthSpec>::default();
+        for subnet_id in &spec.attnets {
+            attnets.set(*subnet_id as usize, true).unwrap();
+        }
+        builder.add_value("attnets".into(), attnets.as_ssz_bytes());
+    }
+    builder.build(enr_key).unwrap()
+}
+
+/// A UDP port on 127.0.0.1 that was free when asked for, so parallel tests
+/// do not collide.
+pub fn free_port() -> u16 {
+    UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|x| x.local_addr())
+        .map(|x| x.port())
+        .unwrap()
+}
+
+/// Parses crawler arguments the way `imp` does, including the top-level
+/// output options the crawler reads.
+pub fn crawler_args<'a>(args: &[&str]) -> ArgMatches<'a> {
+    App::new("imp")
+        .arg(
+            Arg::with_name("output-format")
+                .long("output-format")
+                .takes_value(true),
+        )
+        .subcommand(crawler::cli_app())
+        .get_matches_from(args.iter().map(|x| x.to_string()))
+}
+
+/// Parses replay arguments the way `imp` does.
+pub fn replay_args<'a>(args: &[&str]) -> ArgMatches<'a> {
+    App::new("imp")
+        .arg(
+            Arg::with_name("output-format")
+                .long("output-format")
+                .takes_value(true),
+        )
+        .subcommand(replay::cli_app())
+        .get_matches_from(args.iter().map(|x| x.to_string()))
+}
+
+/// Creates an empty directory for test outputs.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imp-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn line_count(path: &Path) -> usize {
+    std::fs::read_to_string(path)
+        .map(|x| x.lines().count())
+        .unwrap_or(0)
+}
+
+pub fn test_logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
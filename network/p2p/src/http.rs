@@ -0,0 +1,78 @@
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use slog::{info, warn};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio::sync::watch;
+use types::events::Events;
+
+/// Runs an http server on `address` until a shutdown message is received.
+pub async fn serve<F, R>(
+    name: &str,
+    address: SocketAddr,
+    handler: F,
+    mut shutdown_rx: watch::Receiver<Events>,
+    log: slog::Logger,
+) where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Body>, Infallible>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move { Ok::<_, Infallible>(service_fn(handler)) }
+    });
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            warn!(log, "Failed to start {} server on {}: {}", name, address, e);
+            return;
+        }
+    };
+    info!(log, "{} server listening on http://{}", name, address);
+    let shutdown = async move {
+        while let Some(event) = shutdown_rx.recv().await {
+            if let Events::ShutdownMessage = event {
+                break;
+            }
+        }
+    };
+    if let Err(e) = server.with_graceful_shutdown(shutdown).await {
+        warn!(log, "{} server error: {}", name, e);
+    }
+}
+
+pub fn json_response<T: Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("valid response"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+pub fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "error": message }).to_string(),
+        ))
+        .expect("valid response")
+}
+
+pub fn query_params(request: &Request<Body>) -> HashMap<String, String> {
+    request
+        .uri()
+        .query()
+        .map(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
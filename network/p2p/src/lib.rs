@@ -1,5 +1,30 @@
 mod adapter;
+pub mod api;
+pub mod block_tree;
+mod blocks;
+mod chain;
+mod columnar;
+pub mod divergence;
+mod forks;
+mod gossip;
+#[cfg(test)]
+mod harness;
+mod http;
+mod message_id;
+mod metadata;
 pub mod crawler;
+pub mod feed;
+pub mod metrics;
+pub mod output;
+pub mod participation;
+pub mod payload_archive;
+pub mod peer_events;
+pub mod peers;
+pub mod propagation;
+pub mod replay;
+pub mod rotation;
+pub mod slots;
+mod writer;
 pub mod test;
-pub use adapter::Adapter as P2PAdapter;
+pub use adapter::{Adapter as P2PAdapter, ConnectionWatch};
 pub use mothra::cli_app;
@@ -0,0 +1,52 @@
+use sha2::{Digest, Sha256};
+
+/// Prefix of the v1.0 message-id when the payload decompresses.
+const MESSAGE_DOMAIN_VALID_SNAPPY: [u8; 4] = [1, 0, 0, 0];
+/// Prefix of the v1.0 message-id when the payload does not decompress.
+const MESSAGE_DOMAIN_INVALID_SNAPPY: [u8; 4] = [0, 0, 0, 0];
+/// Length of a v1.0 message-id.
+const MESSAGE_ID_LENGTH: usize = 20;
+
+/// The spec computation a received message-id matched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageIdScheme {
+    /// `SHA256(message.data)`, used up to v0.12.
+    Legacy,
+    /// `SHA256(MESSAGE_DOMAIN + payload)[:20]`, used from v1.0.
+    V1,
+}
+
+/// Computes the spec message-ids of a gossip payload. `decompressed` is the
+/// snappy decompressed payload, or `None` if it did not decompress.
+pub fn compute_message_ids(data: &[u8], decompressed: Option<&[u8]>) -> Vec<(MessageIdScheme, Vec<u8>)> {
+    let legacy = Sha256::digest(data).to_vec();
+    let mut hasher = Sha256::new();
+    match decompressed {
+        Some(decompressed) => {
+            hasher.update(&MESSAGE_DOMAIN_VALID_SNAPPY);
+            hasher.update(decompressed);
+        }
+        None => {
+            hasher.update(&MESSAGE_DOMAIN_INVALID_SNAPPY);
+            hasher.update(data);
+        }
+    }
+    let v1 = hasher.finalize()[..MESSAGE_ID_LENGTH].to_vec();
+    vec![(MessageIdScheme::Legacy, legacy), (MessageIdScheme::V1, v1)]
+}
+
+/// Returns the scheme `received` was computed with, or `None` on a mismatch.
+///
+/// Clients have shipped message-ids as url-safe or standard base64 and as
+/// hex, so every encoding is accepted.
+pub fn verify_message_id(received: &str, data: &[u8], decompressed: Option<&[u8]>) -> Option<MessageIdScheme> {
+    compute_message_ids(data, decompressed)
+        .into_iter()
+        .find(|(_, id)| {
+            received == base64::encode_config(id, base64::URL_SAFE_NO_PAD)
+                || received == base64::encode_config(id, base64::URL_SAFE)
+                || received == base64::encode(id)
+                || received.eq_ignore_ascii_case(&hex::encode(id))
+        })
+        .map(|(scheme, _)| scheme)
+}
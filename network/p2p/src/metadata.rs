@@ -0,0 +1,83 @@
+use eth2::libp2p::rpc::methods::{MetaData, Ping};
+use eth2::libp2p::types::EnrBitfield;
+use eth2::types::MainnetEthSpec;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Number of attestation subnets, the length of the `attnets` bitfield.
+pub const ATTESTATION_SUBNET_COUNT: u64 = 64;
+
+/// Parses `--attnets`: `all`, `none` or a comma separated list of subnet ids.
+pub fn parse_attnets(value: &str) -> Result<Vec<u64>, String> {
+    match value {
+        "all" => Ok((0..ATTESTATION_SUBNET_COUNT).collect()),
+        "none" | "" => Ok(vec![]),
+        _ => {
+            let mut subnets = value
+                .split(',')
+                .map(|x| match x.trim().parse::<u64>() {
+                    Ok(subnet) if subnet < ATTESTATION_SUBNET_COUNT => Ok(subnet),
+                    _ => Err(format!("invalid attestation subnet: {:?}", x)),
+                })
+                .collect::<Result<Vec<u64>, String>>()?;
+            subnets.sort_unstable();
+            subnets.dedup();
+            Ok(subnets)
+        }
+    }
+}
+
+/// The MetaData of the previous run, so the sequence number only grows.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct LocalMetaData {
+    pub seq_number: u64,
+    pub attnets: Vec<u64>,
+}
+
+impl LocalMetaData {
+    /// Loads the MetaData stored at `path` and bumps its sequence number if
+    /// the advertised subnets changed, then stores the result. A file that
+    /// does not parse is an error: starting over at sequence number 1 would
+    /// make peers keep the MetaData they cached from the previous run.
+    pub fn load_or_update(path: &Path, attnets: Vec<u64>) -> io::Result<Self> {
+        let previous = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice::<LocalMetaData>(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => LocalMetaData::default(),
+            Err(e) => return Err(e),
+        };
+        if previous.seq_number > 0 && previous.attnets == attnets {
+            return Ok(previous);
+        }
+        let meta_data = LocalMetaData {
+            seq_number: previous.seq_number + 1,
+            attnets,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec(&meta_data)?)?;
+        Ok(meta_data)
+    }
+
+    pub fn meta_data(&self) -> MetaData<MainnetEthSpec> {
+        let mut attnets = EnrBitfield::<MainnetEthSpec>::default();
+        for subnet in &self.attnets {
+            // subnets were validated by parse_attnets
+            let _ = attnets.set(*subnet as usize, true);
+        }
+        MetaData {
+            seq_number: self.seq_number,
+            attnets,
+        }
+    }
+
+    /// Our Ping carries the MetaData sequence number.
+    pub fn ping(&self) -> Ping {
+        Ping {
+            data: self.seq_number,
+        }
+    }
+}
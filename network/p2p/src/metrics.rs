@@ -0,0 +1,229 @@
+use crate::http;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_gauge, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Encoder, Gauge, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::sync::watch;
+use types::events::Events;
+
+lazy_static! {
+    pub static ref CRAWLER_NODES: IntGaugeVec = register_int_gauge_vec!(
+        "imp_crawler_nodes",
+        "Discovered nodes per fork digest",
+        &["fork_digest", "network"]
+    )
+    .expect("valid metric");
+    pub static ref CRAWLER_CONNECTED_PEERS: IntGauge = register_int_gauge!(
+        "imp_crawler_connected_peers",
+        "Connected discv5 peers"
+    )
+    .expect("valid metric");
+    pub static ref CRAWLER_QUERIES: IntCounterVec = register_int_counter_vec!(
+        "imp_crawler_queries_total",
+        "discv5 queries by result",
+        &["result"]
+    )
+    .expect("valid metric");
+    pub static ref GOSSIP_MESSAGES: IntCounterVec = register_int_counter_vec!(
+        "imp_gossip_messages_total",
+        "Gossip messages received per topic",
+        &["topic"]
+    )
+    .expect("valid metric");
+    pub static ref GOSSIP_DECODE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "imp_gossip_decode_errors_total",
+        "Gossip messages that failed to decode per topic",
+        &["topic"]
+    )
+    .expect("valid metric");
+    pub static ref RPC_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "imp_rpc_requests_total",
+        "RPC messages received per method",
+        &["method"]
+    )
+    .expect("valid metric");
+    pub static ref RPC_BLOCKS_SERVED: IntCounterVec = register_int_counter_vec!(
+        "imp_rpc_blocks_served_total",
+        "Blocks sent in response to block requests per method",
+        &["method"]
+    )
+    .expect("valid metric");
+    pub static ref BLOCK_STORE_BLOCKS: IntGauge = register_int_gauge!(
+        "imp_block_store_blocks",
+        "Blocks kept to answer BlocksByRange and BlocksByRoot"
+    )
+    .expect("valid metric");
+    pub static ref AGENT_PEERS: IntGauge = register_int_gauge!(
+        "imp_agent_peers",
+        "Connected peers in the agent's peer table"
+    )
+    .expect("valid metric");
+    pub static ref PEER_GOODBYES: IntCounterVec = register_int_counter_vec!(
+        "imp_peer_goodbyes_total",
+        "Goodbyes received per client and reason",
+        &["agent", "reason"]
+    )
+    .expect("valid metric");
+    pub static ref PEER_DISCONNECTS: IntCounterVec = register_int_counter_vec!(
+        "imp_peer_disconnects_total",
+        "Peers disconnected without a Goodbye from them per client",
+        &["agent"]
+    )
+    .expect("valid metric");
+    pub static ref PEER_DIAL_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "imp_peer_dial_failures_total",
+        "Dials that ended without a connection per client",
+        &["agent"]
+    )
+    .expect("valid metric");
+    pub static ref PEER_CONNECTION_SECONDS: HistogramVec = register_histogram_vec!(
+        "imp_peer_connection_seconds",
+        "Time between a peer connecting and the connection ending per client",
+        &["agent"],
+        exponential_buckets(1.0, 4.0, 10).expect("valid buckets")
+    )
+    .expect("valid metric");
+    pub static ref FINALIZED_DIVERGENCES: IntGauge = register_int_gauge!(
+        "imp_finalized_divergences",
+        "Epochs at which significant groups of peers finalized different roots"
+    )
+    .expect("valid metric");
+    pub static ref REORGS: IntCounter = register_int_counter!(
+        "imp_reorgs_total",
+        "Head switches to a block that does not extend the previous head"
+    )
+    .expect("valid metric");
+    pub static ref REORG_DEPTH: Histogram = register_histogram!(
+        "imp_reorg_depth",
+        "Blocks of the previous head's branch that left the canonical chain",
+        vec![1.0, 2.0, 3.0, 4.0, 8.0, 16.0, 32.0]
+    )
+    .expect("valid metric");
+    pub static ref ORPHANED_BLOCKS: IntCounter = register_int_counter!(
+        "imp_orphaned_blocks_total",
+        "Blocks that did not end up on the canonical chain"
+    )
+    .expect("valid metric");
+    pub static ref ATTESTATION_PARTICIPATION: Gauge = register_gauge!(
+        "imp_attestation_participation",
+        "Share of the validators of the observed committees that voted in the last completed epoch"
+    )
+    .expect("valid metric");
+    pub static ref ATTESTATION_TARGET_ROOTS: IntGauge = register_int_gauge!(
+        "imp_attestation_target_roots",
+        "Distinct target roots voted for in the last completed epoch"
+    )
+    .expect("valid metric");
+    pub static ref SLOTS: IntCounterVec = register_int_counter_vec!(
+        "imp_slots_total",
+        "Settled slots by outcome, proposed or missed",
+        &["outcome"]
+    )
+    .expect("valid metric");
+    pub static ref BLOCK_TIMELINESS: IntCounterVec = register_int_counter_vec!(
+        "imp_block_timeliness_total",
+        "First blocks of settled slots by arrival, early, on_time or late",
+        &["timeliness"]
+    )
+    .expect("valid metric");
+    pub static ref GOSSIP_MESSAGE_ID_MISMATCHES: IntCounterVec = register_int_counter_vec!(
+        "imp_gossip_message_id_mismatches_total",
+        "Gossip messages whose message-id does not match the spec computation",
+        &["agent"]
+    )
+    .expect("valid metric");
+    pub static ref GOSSIP_DUPLICATES: IntCounterVec = register_int_counter_vec!(
+        "imp_gossip_duplicates_total",
+        "Repeated deliveries of an already seen message id per topic",
+        &["topic"]
+    )
+    .expect("valid metric");
+    pub static ref GOSSIP_FIRST_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "imp_gossip_first_deliveries_total",
+        "Messages delivered first per client, taken from the agent string",
+        &["agent"]
+    )
+    .expect("valid metric");
+    pub static ref GOSSIP_ARRIVAL_DELAY: HistogramVec = register_histogram_vec!(
+        "imp_gossip_arrival_delay_seconds",
+        "Gossip arrival time relative to the start of the message's slot",
+        &["topic", "agent"],
+        vec![-1.0, 0.0, 0.5, 1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 24.0]
+    )
+    .expect("valid metric");
+    pub static ref WRITER_QUEUE_LENGTH: IntGaugeVec = register_int_gauge_vec!(
+        "imp_writer_queue_length",
+        "Records waiting for the writer thread",
+        &["writer"]
+    )
+    .expect("valid metric");
+    pub static ref WRITER_BATCH_SIZE: HistogramVec = register_histogram_vec!(
+        "imp_writer_batch_size",
+        "Records written per batch by the writer thread",
+        &["writer"],
+        exponential_buckets(1.0, 4.0, 6).expect("valid buckets")
+    )
+    .expect("valid metric");
+    pub static ref WRITER_STALL_SECONDS: HistogramVec = register_histogram_vec!(
+        "imp_writer_stall_seconds",
+        "Time producers waited because the writer queue was full",
+        &["writer"]
+    )
+    .expect("valid metric");
+    pub static ref WRITER_DROPPED_RECORDS: IntCounterVec = register_int_counter_vec!(
+        "imp_writer_dropped_records_total",
+        "Records dropped because the writer thread had stopped",
+        &["writer"]
+    )
+    .expect("valid metric");
+    pub static ref PEERS_BY_AGENT: IntGaugeVec = register_int_gauge_vec!(
+        "imp_peers_by_agent",
+        "Connected peers per client, taken from the agent string",
+        &["agent"]
+    )
+    .expect("valid metric");
+}
+
+/// Returns the topic name without the fork digest and encoding,
+/// e.g. `/eth2/f6775d07/beacon_block/ssz_snappy` becomes `beacon_block`.
+pub fn topic_kind(topic: &str) -> &str {
+    topic.split('/').nth(3).unwrap_or(topic)
+}
+
+/// Returns the client name from an agent string,
+/// e.g. `Lighthouse/v0.2.0-5a088b19/x86_64-linux` becomes `Lighthouse`.
+pub fn agent_client(agent_string: &str) -> &str {
+    match agent_string.split('/').next() {
+        Some(client) if !client.is_empty() => client,
+        _ => "unknown",
+    }
+}
+
+/// Serves `/metrics` until a shutdown message is received.
+pub async fn serve(address: SocketAddr, shutdown_rx: watch::Receiver<Events>, log: slog::Logger) {
+    http::serve("Metrics", address, handle_request, shutdown_rx, log).await
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            let encoder = TextEncoder::new();
+            let mut buffer = vec![];
+            match encoder.encode(&prometheus::gather(), &mut buffer) {
+                Ok(_) => Response::builder()
+                    .header(CONTENT_TYPE, encoder.format_type())
+                    .body(Body::from(buffer))
+                    .expect("valid response"),
+                Err(e) => http::error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            }
+        }
+        _ => http::error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+    Ok(response)
+}
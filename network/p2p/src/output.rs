@@ -0,0 +1,221 @@
+use crate::rotation::Rotation;
+use crate::writer::RecordSink;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+pub const OUTPUT_FORMATS: &[&str] = &["csv", "jsonl", "stdout", "parquet"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Comma separated values with a header row
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+    /// One JSON object per line, streamed to stdout
+    Stdout,
+    /// Typed columnar files, see `columnar::ParquetSink`
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Stdout => "",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+
+    /// Streaming formats emit records as they are produced rather than as
+    /// periodic snapshots.
+    pub fn is_streaming(&self) -> bool {
+        *self == OutputFormat::Stdout
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Csv
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "stdout" => Ok(OutputFormat::Stdout),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+enum Writer {
+    Csv(csv::Writer<File>),
+    Jsonl(BufWriter<File>),
+    Stdout(Stdout),
+}
+
+impl Writer {
+    fn open(format: OutputFormat, path: &Path, truncate: bool) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .append(!truncate)
+            .open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        Ok(match format {
+            OutputFormat::Csv => Writer::Csv(
+                csv::WriterBuilder::new()
+                    .has_headers(is_empty)
+                    .from_writer(file),
+            ),
+            _ => Writer::Jsonl(BufWriter::new(file)),
+        })
+    }
+}
+
+/// Destination for serialized records.
+pub struct Sink {
+    format: OutputFormat,
+    path: Option<PathBuf>,
+    writer: Writer,
+    rotation: Rotation,
+    opened_at: Instant,
+}
+
+impl Sink {
+    /// Opens `<dir>/<name>.<ext>` for the given format, creating `dir` if needed.
+    /// When `truncate` is false, records are appended to an existing file and
+    /// the csv header is only written if the file is empty.
+    pub fn create(format: OutputFormat, dir: &Path, name: &str, truncate: bool) -> io::Result<Self> {
+        if format == OutputFormat::Parquet {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "parquet output requires a ParquetSink",
+            ));
+        }
+        if format == OutputFormat::Stdout {
+            return Ok(Sink {
+                format,
+                path: None,
+                writer: Writer::Stdout(io::stdout()),
+                rotation: Rotation::default(),
+                opened_at: Instant::now(),
+            });
+        }
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.{}", name, format.extension()));
+        let writer = Writer::open(format, &path, truncate)?;
+        Ok(Sink {
+            format,
+            path: Some(path),
+            writer,
+            rotation: Rotation::default(),
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Archives the file and starts a new one whenever the rollover policy
+    /// is due. The policy is checked on every flush.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Csv(wtr) => wtr.serialize(record).map_err(csv_error),
+            Writer::Jsonl(wtr) => write_json_line(wtr, record),
+            Writer::Stdout(wtr) => write_json_line(&mut wtr.lock(), record),
+        }
+    }
+
+    /// Writes two records as a single row: csv columns are concatenated and
+    /// json objects are merged.
+    pub fn write_pair<A: Serialize, B: Serialize>(&mut self, a: &A, b: &B) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Csv(wtr) => wtr.serialize((a, b)).map_err(csv_error),
+            _ => {
+                let value = merge_json(a, b)?;
+                self.write(&value)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flush_writer()?;
+        if let Some(path) = &self.path {
+            if self.rotation.rollover.is_enabled() {
+                let bytes = fs::metadata(path)?.len();
+                if self.rotation.rollover.is_due(bytes, self.opened_at.elapsed()) {
+                    self.rotation.archive(path)?;
+                    self.writer = Writer::open(self.format, path, true)?;
+                    self.opened_at = Instant::now();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_writer(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Csv(wtr) => wtr.flush(),
+            Writer::Jsonl(wtr) => wtr.flush(),
+            Writer::Stdout(wtr) => wtr.flush(),
+        }
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        let _ = self.flush_writer();
+    }
+}
+
+impl<R: Serialize + Send + 'static> RecordSink<R> for Sink {
+    fn write(&mut self, record: R) -> io::Result<()> {
+        Sink::write(self, &record)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Sink::flush(self)
+    }
+}
+
+/// Serializes both records and merges the fields of `b` into `a`.
+pub fn merge_json<A: Serialize, B: Serialize>(a: &A, b: &B) -> serde_json::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(a)?;
+    if let (Some(object), serde_json::Value::Object(other)) =
+        (value.as_object_mut(), serde_json::to_value(b)?)
+    {
+        object.extend(other);
+    }
+    Ok(value)
+}
+
+fn write_json_line<W: Write, T: Serialize>(wtr: &mut W, record: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *wtr, record)?;
+    wtr.write_all(b"\n")
+}
+
+fn csv_error(e: csv::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
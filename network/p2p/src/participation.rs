@@ -0,0 +1,218 @@
+use crate::metrics;
+use eth2::types::{EthSpec, Hash256, MainnetEthSpec};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+/// Epochs after its own in which an attestation may still be included in a
+/// block. Once they have passed, the votes of an epoch are complete.
+const OPEN_EPOCHS: u64 = 1;
+/// Completed epochs kept for the api.
+const RECENT_EPOCHS: usize = 256;
+/// Committees per slot in the spec, which bounds the committees kept per
+/// epoch.
+const MAX_COMMITTEES_PER_SLOT: u64 = 64;
+
+/// Estimated participation of one slot.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SlotParticipation {
+    pub slot: u64,
+    /// Committees imp saw votes from.
+    pub committees: u64,
+    /// Validators in those committees.
+    pub validators: u64,
+    /// Validators whose vote imp saw in an aggregate or attestation.
+    pub attesters: u64,
+    pub rate: f64,
+}
+
+/// Attesters of an epoch voting for one target root.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TargetVotes {
+    pub target_root: String,
+    pub attesters: u64,
+}
+
+/// Estimated participation of one epoch.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct EpochParticipation {
+    pub epoch: u64,
+    /// False while votes for the epoch can still arrive.
+    pub complete: bool,
+    pub committees: u64,
+    pub validators: u64,
+    pub attesters: u64,
+    pub rate: f64,
+    /// Target roots, most attesters first. More than one root means the
+    /// validators disagree on the chain.
+    pub targets: Vec<TargetVotes>,
+    pub slots: Vec<SlotParticipation>,
+}
+
+#[derive(Default)]
+struct Committee {
+    /// Union of the aggregation bits seen.
+    bits: Vec<bool>,
+    /// Union of the aggregation bits per target root.
+    targets: HashMap<Hash256, Vec<bool>>,
+}
+
+/// Estimates attestation participation from the aggregation bits of the
+/// aggregates and attestations imp sees.
+///
+/// Committees are only known from their votes: without the beacon state imp
+/// cannot tell how many committees a slot has, so a committee nobody
+/// aggregated for is missing rather than counted as absent, and rates are
+/// relative to the committees seen.
+#[derive(Default)]
+pub struct Participation {
+    committees: BTreeMap<(u64, u64), Committee>,
+    newest_epoch: Option<u64>,
+    recent_epochs: VecDeque<EpochParticipation>,
+}
+
+pub type SharedParticipation = Arc<RwLock<Participation>>;
+
+impl Participation {
+    /// Adds the votes of an aggregate or attestation. Votes whose target is
+    /// not the epoch of their slot, for a committee index the spec does not
+    /// have, or that arrive after their epoch is complete, are ignored.
+    /// Callers drop votes from future slots, which would complete every
+    /// epoch before them.
+    pub fn on_attestation(
+        &mut self,
+        slot: u64,
+        committee_index: u64,
+        target_epoch: u64,
+        target_root: Hash256,
+        bits: &[bool],
+    ) {
+        let epoch = slot / MainnetEthSpec::slots_per_epoch();
+        if target_epoch != epoch
+            || committee_index >= MAX_COMMITTEES_PER_SLOT
+            || bits.is_empty()
+        {
+            return;
+        }
+        let newest_epoch = self.newest_epoch.map_or(epoch, |newest| newest.max(epoch));
+        if epoch + OPEN_EPOCHS < newest_epoch {
+            return;
+        }
+        self.newest_epoch = Some(newest_epoch);
+        let committee = self.committees.entry((slot, committee_index)).or_default();
+        union(&mut committee.bits, bits);
+        union(committee.targets.entry(target_root).or_default(), bits);
+        self.complete(newest_epoch);
+    }
+
+    /// Summarises and drops the epochs that can no longer receive votes.
+    fn complete(&mut self, newest_epoch: u64) {
+        let first_open_slot = match newest_epoch.checked_sub(OPEN_EPOCHS) {
+            Some(epoch) => epoch * MainnetEthSpec::slots_per_epoch(),
+            None => return,
+        };
+        let open = self.committees.split_off(&(first_open_slot, 0));
+        let completed = std::mem::replace(&mut self.committees, open);
+        for (epoch, committees) in by_epoch(&completed) {
+            let summary = summarize(epoch, true, committees);
+            metrics::ATTESTATION_PARTICIPATION.set(summary.rate);
+            metrics::ATTESTATION_TARGET_ROOTS.set(summary.targets.len() as i64);
+            self.recent_epochs.push_back(summary);
+            if self.recent_epochs.len() > RECENT_EPOCHS {
+                self.recent_epochs.pop_front();
+            }
+        }
+    }
+
+    /// The open epochs followed by the most recently completed ones, newest
+    /// first.
+    pub fn epochs(&self, limit: usize) -> Vec<EpochParticipation> {
+        let mut epochs: Vec<EpochParticipation> = by_epoch(&self.committees)
+            .into_iter()
+            .rev()
+            .map(|(epoch, committees)| summarize(epoch, false, committees))
+            .collect();
+        epochs.extend(self.recent_epochs.iter().rev().cloned());
+        epochs.truncate(limit);
+        epochs
+    }
+}
+
+fn union(into: &mut Vec<bool>, bits: &[bool]) {
+    if into.len() < bits.len() {
+        into.resize(bits.len(), false);
+    }
+    for (into, bit) in into.iter_mut().zip(bits) {
+        *into |= *bit;
+    }
+}
+
+fn count(bits: &[bool]) -> u64 {
+    bits.iter().filter(|bit| **bit).count() as u64
+}
+
+fn rate(attesters: u64, validators: u64) -> f64 {
+    if validators == 0 {
+        0.0
+    } else {
+        attesters as f64 / validators as f64
+    }
+}
+
+type EpochCommittees<'a> = Vec<(u64, &'a Committee)>;
+
+fn by_epoch(committees: &BTreeMap<(u64, u64), Committee>) -> BTreeMap<u64, EpochCommittees<'_>> {
+    let mut epochs: BTreeMap<u64, EpochCommittees<'_>> = BTreeMap::new();
+    for (&(slot, _), committee) in committees {
+        epochs
+            .entry(slot / MainnetEthSpec::slots_per_epoch())
+            .or_default()
+            .push((slot, committee));
+    }
+    epochs
+}
+
+/// Sums the committees of an epoch, which come ordered by slot.
+fn summarize(epoch: u64, complete: bool, committees: EpochCommittees<'_>) -> EpochParticipation {
+    let mut summary = EpochParticipation {
+        epoch,
+        complete,
+        ..Default::default()
+    };
+    let mut targets: HashMap<Hash256, u64> = HashMap::new();
+    for (slot, committee) in committees {
+        if summary.slots.last().map(|x| x.slot) != Some(slot) {
+            summary.slots.push(SlotParticipation {
+                slot,
+                ..Default::default()
+            });
+        }
+        let slot = summary.slots.last_mut().expect("pushed above");
+        slot.committees += 1;
+        slot.validators += committee.bits.len() as u64;
+        slot.attesters += count(&committee.bits);
+        for (root, bits) in &committee.targets {
+            *targets.entry(*root).or_default() += count(bits);
+        }
+    }
+    for slot in &mut summary.slots {
+        slot.rate = rate(slot.attesters, slot.validators);
+        summary.committees += slot.committees;
+        summary.validators += slot.validators;
+        summary.attesters += slot.attesters;
+    }
+    summary.rate = rate(summary.attesters, summary.validators);
+    summary.targets = targets
+        .into_iter()
+        .map(|(root, attesters)| TargetVotes {
+            target_root: format!("{:?}", root),
+            attesters,
+        })
+        .collect();
+    summary.targets.sort_by(|a, b| {
+        b.attesters
+            .cmp(&a.attesters)
+            .then(a.target_root.cmp(&b.target_root))
+    });
+    summary
+}
@@ -0,0 +1,134 @@
+use crate::output::{OutputFormat, Sink};
+use crate::writer::RecordSink;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Directory below the archive root holding one file per unique payload.
+const PAYLOAD_DIR: &str = "payloads";
+/// Name of the per-arrival index, written as `index.jsonl`.
+const INDEX_NAME: &str = "index";
+
+/// One delivery of a gossip message. The payload itself is stored once per
+/// unique content under `payloads/`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArrivalEntry {
+    pub arrival_ms: u64,
+    pub message_id: String,
+    pub sequence_number: u64,
+    pub agent_string: String,
+    pub peer_id: String,
+    pub topic: String,
+    /// Hex SHA256 of the raw `ssz_snappy` payload.
+    pub payload: String,
+}
+
+/// A gossip delivery queued for the archive's writer thread.
+pub struct Arrival {
+    pub arrival_ms: u64,
+    pub message_id: String,
+    pub sequence_number: u64,
+    pub agent_string: String,
+    pub peer_id: String,
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+/// Content-addressed store of raw gossip payloads with an arrival index.
+/// It is written on a writer thread, which flushes the index once per
+/// batch of arrivals.
+///
+/// Layout:
+/// ```text
+/// <dir>/index.jsonl
+/// <dir>/payloads/<first two hex chars>/<sha256>.ssz_snappy
+/// ```
+pub struct PayloadArchive {
+    dir: PathBuf,
+    index: Sink,
+}
+
+impl PayloadArchive {
+    /// Opens the archive at `dir`, appending to an existing index.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir.join(PAYLOAD_DIR))?;
+        let index = Sink::create(OutputFormat::Jsonl, dir, INDEX_NAME, false)?;
+        Ok(PayloadArchive {
+            dir: dir.to_path_buf(),
+            index,
+        })
+    }
+
+    /// Stores the payload unless the same payload was stored before, and
+    /// records the arrival in the index.
+    pub fn store(&mut self, arrival: Arrival) -> io::Result<()> {
+        let payload = hex::encode(Sha256::digest(&arrival.data));
+        let path = payload_path(&self.dir, &payload)?;
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // write to a temporary file first so readers never see partial payloads
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, &arrival.data)?;
+            fs::rename(&tmp_path, &path)?;
+        }
+        self.index.write(&ArrivalEntry {
+            arrival_ms: arrival.arrival_ms,
+            message_id: arrival.message_id,
+            sequence_number: arrival.sequence_number,
+            agent_string: arrival.agent_string,
+            peer_id: arrival.peer_id,
+            topic: arrival.topic,
+            payload,
+        })
+    }
+}
+
+impl RecordSink<Arrival> for PayloadArchive {
+    fn write(&mut self, arrival: Arrival) -> io::Result<()> {
+        self.store(arrival)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.index.flush()
+    }
+}
+
+/// Path of the payload with hex SHA256 `payload`. Hashes from an edited or
+/// truncated index are rejected rather than turned into arbitrary paths.
+pub fn payload_path(dir: &Path, payload: &str) -> io::Result<PathBuf> {
+    if payload.len() != 64 || !payload.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid payload hash: {:?}", payload),
+        ));
+    }
+    Ok(dir
+        .join(PAYLOAD_DIR)
+        .join(&payload[..2])
+        .join(format!("{}.ssz_snappy", payload)))
+}
+
+/// Reads the arrival index of the archive at `dir`, in arrival order.
+pub fn read_index(dir: &Path) -> io::Result<Vec<ArrivalEntry>> {
+    let file = File::open(dir.join(format!("{}.jsonl", INDEX_NAME)))?;
+    let mut entries = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Loads the raw payload of an index entry.
+pub fn load_payload(dir: &Path, entry: &ArrivalEntry) -> io::Result<Vec<u8>> {
+    fs::read(payload_path(dir, &entry.payload)?)
+}
@@ -0,0 +1,70 @@
+use crate::peers::PeerRecord;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// A change in the connection state of a peer, as written to
+/// `peer_events.jsonl`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PeerEvent {
+    /// Unix time in milliseconds.
+    pub timestamp: u64,
+    pub peer_id: String,
+    pub agent_string: String,
+    /// `connected`, `goodbye_received`, `disconnected` or `dial_failed`.
+    pub event: &'static str,
+    pub reason_code: Option<u64>,
+    pub reason: Option<&'static str>,
+    /// How long the peer was connected, if imp saw it connect and the
+    /// connection ends with this event.
+    pub duration_ms: Option<u64>,
+}
+
+/// Returns the name of a Goodbye reason code. Codes from 128 on are client
+/// specific, the names are the ones Lighthouse and Prysm use.
+pub fn goodbye_reason(code: u64) -> &'static str {
+    match code {
+        1 => "client_shutdown",
+        2 => "irrelevant_network",
+        3 => "fault",
+        128 => "unable_to_verify_network",
+        129 => "too_many_peers",
+        250 => "bad_score",
+        251 => "banned",
+        _ => "unknown",
+    }
+}
+
+/// Connections, Goodbyes and disconnects of all peers of one client.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ConnectionStats {
+    pub peers: u64,
+    pub connections: u64,
+    /// Received Goodbyes per reason.
+    pub goodbyes: BTreeMap<&'static str, u64>,
+    /// Connections that ended without a Goodbye from the peer.
+    pub disconnects: u64,
+    pub dial_failures: u64,
+    /// Mean time between connecting and the connection ending.
+    pub mean_duration_ms: Option<u64>,
+    #[serde(skip_serializing)]
+    total_duration_ms: u64,
+    #[serde(skip_serializing)]
+    timed_connections: u64,
+}
+
+impl ConnectionStats {
+    pub fn add(&mut self, peer: &PeerRecord) {
+        self.peers += 1;
+        self.connections += peer.connections;
+        for (reason, count) in &peer.goodbye_reasons {
+            *self.goodbyes.entry(reason).or_default() += count;
+        }
+        self.disconnects += peer.disconnects;
+        self.dial_failures += peer.dial_failures;
+        self.total_duration_ms += peer.connected_ms;
+        self.timed_connections += peer.timed_connections;
+        if self.timed_connections > 0 {
+            self.mean_duration_ms = Some(self.total_duration_ms / self.timed_connections);
+        }
+    }
+}
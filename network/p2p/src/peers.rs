@@ -0,0 +1,540 @@
+use crate::columnar::{ParquetRecord, ParquetSink};
+use crate::divergence::{checkpoint_groups, divergences, CheckpointGroup};
+use crate::metrics;
+use crate::output::{OutputFormat, Sink};
+use crate::peer_events::{goodbye_reason, ConnectionStats, PeerEvent};
+use crate::rotation::Rotation;
+use arrow::array::{
+    ArrayRef, BooleanBuilder, ListBuilder, StringBuilder, TimestampMillisecondBuilder, UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::Result as ArrowResult;
+use arrow::record_batch::RecordBatch;
+use eth2::libp2p::rpc::methods::{MetaData, StatusMessage};
+use eth2::types::MainnetEthSpec;
+use serde_derive::Serialize;
+use slog::{info, warn};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use futures::StreamExt;
+use tokio::sync::watch;
+use types::events::Events;
+
+/// Name of the peer table snapshot, written as `peers.<format>`.
+const SNAPSHOT_NAME: &str = "peers";
+/// How long disconnected peers stay in the table.
+const DISCONNECTED_PEER_RETENTION_MS: u64 = 10 * 60 * 1000;
+/// Connected peers silent for longer are dropped too, in case imp missed
+/// their disconnect.
+const SILENT_PEER_RETENTION_MS: u64 = 60 * 60 * 1000;
+
+/// What imp knows about a peer. Missing values are filled in as the peer
+/// sends gossip, Status or MetaData.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct PeerRecord {
+    pub peer_id: String,
+    pub agent_string: String,
+    /// Whether the peer is connected, as far as imp knows.
+    pub connected: bool,
+    /// Unix time in milliseconds the peer connected, if imp saw it connect.
+    pub connected_at: Option<u64>,
+    /// Unix time in milliseconds of the last message from the peer.
+    pub last_seen: u64,
+    pub fork_digest: Option<String>,
+    pub head_slot: Option<u64>,
+    pub head_root: Option<String>,
+    pub finalized_epoch: Option<u64>,
+    pub finalized_root: Option<String>,
+    pub metadata_seq_number: Option<u64>,
+    /// Attestation subnets from the peer's MetaData, comma separated.
+    pub attnets: String,
+    pub gossip_messages: u64,
+    pub gossip_blocks: u64,
+    pub gossip_aggregates: u64,
+    pub gossip_attestations: u64,
+    pub connections: u64,
+    pub goodbyes: u64,
+    pub last_goodbye: Option<&'static str>,
+    /// Connections that ended without a Goodbye from the peer.
+    pub disconnects: u64,
+    pub dial_failures: u64,
+    #[serde(skip_serializing)]
+    pub subnets: Vec<u64>,
+    #[serde(skip_serializing)]
+    pub goodbye_reasons: BTreeMap<&'static str, u64>,
+    /// Summed duration of the connections imp saw start and end.
+    #[serde(skip_serializing)]
+    pub connected_ms: u64,
+    #[serde(skip_serializing)]
+    pub timed_connections: u64,
+}
+
+impl PeerRecord {
+    /// Ends the current connection and returns its duration, if imp saw it
+    /// start.
+    fn end_connection(&mut self, now_ms: u64) -> Option<u64> {
+        let duration_ms = self
+            .connected_at
+            .take()
+            .map(|connected_at| now_ms.saturating_sub(connected_at));
+        if let Some(duration_ms) = duration_ms {
+            self.connected_ms += duration_ms;
+            self.timed_connections += 1;
+        }
+        duration_ms
+    }
+}
+
+/// Peers of the agent keyed by their base58 PeerId. Peers that left are
+/// dropped by `expire`.
+#[derive(Default)]
+pub struct PeerTable {
+    peers: HashMap<String, PeerRecord>,
+    /// Connection stats of the dropped peers per client.
+    expired: BTreeMap<String, ConnectionStats>,
+}
+
+pub type SharedPeerTable = Arc<RwLock<PeerTable>>;
+
+impl PeerTable {
+    fn entry(&mut self, peer_id: &str, now_ms: u64) -> &mut PeerRecord {
+        let peer = self
+            .peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerRecord {
+                peer_id: peer_id.to_string(),
+                ..Default::default()
+            });
+        peer.last_seen = now_ms;
+        peer
+    }
+
+    pub fn on_connected(&mut self, peer_id: &str, now_ms: u64) -> PeerEvent {
+        let peer = self.entry(peer_id, now_ms);
+        peer.connected = true;
+        peer.connected_at = Some(now_ms);
+        peer.connections += 1;
+        let event = PeerEvent {
+            timestamp: now_ms,
+            peer_id: peer_id.to_string(),
+            agent_string: peer.agent_string.clone(),
+            event: "connected",
+            reason_code: None,
+            reason: None,
+            duration_ms: None,
+        };
+        self.update_metrics();
+        event
+    }
+
+    /// Records a Goodbye from the peer. The connection ends with it.
+    pub fn on_goodbye(&mut self, peer_id: &str, reason_code: u64, now_ms: u64) -> PeerEvent {
+        let peer = self.entry(peer_id, now_ms);
+        let reason = goodbye_reason(reason_code);
+        peer.connected = false;
+        let duration_ms = peer.end_connection(now_ms);
+        peer.goodbyes += 1;
+        peer.last_goodbye = Some(reason);
+        *peer.goodbye_reasons.entry(reason).or_default() += 1;
+        let event = PeerEvent {
+            timestamp: now_ms,
+            peer_id: peer_id.to_string(),
+            agent_string: peer.agent_string.clone(),
+            event: "goodbye_received",
+            reason_code: Some(reason_code),
+            reason: Some(reason),
+            duration_ms,
+        };
+        self.update_metrics();
+        event
+    }
+
+    /// Records the end of a connection. Connections the peer ended with a
+    /// Goodbye were already closed by `on_goodbye`, the others were dropped
+    /// or ended by a Goodbye imp sent, which mothra does not tell apart.
+    pub fn on_disconnected(&mut self, peer_id: &str, now_ms: u64) -> Option<PeerEvent> {
+        let peer = self.peers.get_mut(peer_id).filter(|peer| peer.connected)?;
+        peer.connected = false;
+        peer.last_seen = now_ms;
+        let duration_ms = peer.end_connection(now_ms);
+        peer.disconnects += 1;
+        let event = PeerEvent {
+            timestamp: now_ms,
+            peer_id: peer_id.to_string(),
+            agent_string: peer.agent_string.clone(),
+            event: "disconnected",
+            reason_code: None,
+            reason: None,
+            duration_ms,
+        };
+        self.update_metrics();
+        Some(event)
+    }
+
+    /// Records a dial that ended without a connection.
+    pub fn on_dial_failed(&mut self, peer_id: &str, now_ms: u64) -> PeerEvent {
+        let peer = self.entry(peer_id, now_ms);
+        peer.dial_failures += 1;
+        PeerEvent {
+            timestamp: now_ms,
+            peer_id: peer_id.to_string(),
+            agent_string: peer.agent_string.clone(),
+            event: "dial_failed",
+            reason_code: None,
+            reason: None,
+            duration_ms: None,
+        }
+    }
+
+    pub fn on_gossip(&mut self, peer_id: &str, agent_string: &str, topic: &str, now_ms: u64) {
+        let peer = self.entry(peer_id, now_ms);
+        // gossip only arrives over a connection
+        let mut changed = !peer.connected;
+        peer.connected = true;
+        if peer.agent_string.is_empty() {
+            peer.agent_string = agent_string.to_string();
+            changed = true;
+        }
+        peer.gossip_messages += 1;
+        match metrics::topic_kind(topic) {
+            "beacon_block" => peer.gossip_blocks += 1,
+            "beacon_aggregate_and_proof" => peer.gossip_aggregates += 1,
+            kind if kind.starts_with("beacon_attestation") => peer.gossip_attestations += 1,
+            _ => {}
+        }
+        if changed {
+            self.update_metrics();
+        }
+    }
+
+    /// Drops peers that disconnected more than
+    /// `DISCONNECTED_PEER_RETENTION_MS` ago or have been silent for more than
+    /// `SILENT_PEER_RETENTION_MS`, keeping their connection stats.
+    pub fn expire(&mut self, now_ms: u64) {
+        let expired: Vec<String> = self
+            .peers
+            .values()
+            .filter(|peer| {
+                let retention = if peer.connected {
+                    SILENT_PEER_RETENTION_MS
+                } else {
+                    DISCONNECTED_PEER_RETENTION_MS
+                };
+                peer.last_seen.saturating_add(retention) < now_ms
+            })
+            .map(|peer| peer.peer_id.clone())
+            .collect();
+        for peer_id in expired {
+            if let Some(peer) = self.peers.remove(&peer_id) {
+                self.expired
+                    .entry(metrics::agent_client(&peer.agent_string).to_string())
+                    .or_default()
+                    .add(&peer);
+            }
+        }
+        self.update_metrics();
+    }
+
+    fn update_metrics(&self) {
+        let mut by_agent: HashMap<&str, i64> = HashMap::new();
+        for peer in self.peers.values().filter(|peer| peer.connected) {
+            *by_agent.entry(metrics::agent_client(&peer.agent_string)).or_default() += 1;
+        }
+        metrics::AGENT_PEERS.set(by_agent.values().sum());
+        // clients without connected peers drop out of the gauge
+        metrics::PEERS_BY_AGENT.reset();
+        for (agent, peers) in by_agent {
+            metrics::PEERS_BY_AGENT.with_label_values(&[agent]).set(peers);
+        }
+    }
+
+    pub fn on_status(&mut self, peer_id: &str, status: &StatusMessage, now_ms: u64) {
+        let peer = self.entry(peer_id, now_ms);
+        peer.fork_digest = Some(hex::encode(status.fork_digest));
+        peer.head_slot = Some(status.head_slot.as_u64());
+        peer.head_root = Some(format!("{:?}", status.head_root));
+        peer.finalized_epoch = Some(status.finalized_epoch.as_u64());
+        peer.finalized_root = Some(format!("{:?}", status.finalized_root));
+    }
+
+    pub fn on_meta_data(&mut self, peer_id: &str, meta_data: &MetaData<MainnetEthSpec>, now_ms: u64) {
+        let peer = self.entry(peer_id, now_ms);
+        peer.metadata_seq_number = Some(meta_data.seq_number);
+        peer.subnets = (0..meta_data.attnets.len())
+            .filter(|i| meta_data.attnets.get(*i).unwrap_or(false))
+            .map(|i| i as u64)
+            .collect();
+        peer.attnets = peer
+            .subnets
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Connected peers grouped by the finalized checkpoint of their latest
+    /// Status.
+    pub fn checkpoints(&self) -> Vec<CheckpointGroup> {
+        checkpoint_groups(self.peers.values().filter(|peer| peer.connected))
+    }
+
+    pub fn peer(&self, peer_id: &str) -> Option<&PeerRecord> {
+        self.peers.get(peer_id)
+    }
+
+    /// Returns the peers ordered by PeerId, optionally only those of one
+    /// client, e.g. `Lighthouse`.
+    pub fn peers(&self, client: Option<&str>) -> Vec<&PeerRecord> {
+        let mut peers: Vec<&PeerRecord> = self
+            .peers
+            .values()
+            .filter(|peer| {
+                client.map_or(true, |client| {
+                    metrics::agent_client(&peer.agent_string).eq_ignore_ascii_case(client)
+                })
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        peers
+    }
+
+    /// Aggregates connections and Goodbyes per client, including the peers
+    /// dropped from the table. Peers are grouped by their current agent
+    /// string, which is often only known after they connected.
+    pub fn connection_stats(&self) -> BTreeMap<String, ConnectionStats> {
+        let mut stats = self.expired.clone();
+        for peer in self.peers.values() {
+            stats
+                .entry(metrics::agent_client(&peer.agent_string).to_string())
+                .or_default()
+                .add(peer);
+        }
+        stats
+    }
+}
+
+/// Writes the peer table to `<dir>/peers.<format>` at a fixed interval and
+/// warns when peers disagree on a finalized checkpoint. Peers that left are
+/// expired from the table before each snapshot.
+pub struct PeerSnapshots {
+    pub peers: SharedPeerTable,
+    pub dir: PathBuf,
+    pub output_format: OutputFormat,
+    pub interval: Duration,
+}
+
+impl PeerSnapshots {
+    /// Writes a snapshot every interval and a last one on shutdown.
+    pub async fn run(self, mut shutdown_rx: watch::Receiver<Events>, log: slog::Logger) {
+        let mut interval = tokio::time::interval(self.interval);
+        // (fork digest, epoch) of the splits already reported
+        let mut reported = HashSet::new();
+        loop {
+            tokio::select! {
+                x = shutdown_rx.recv() => {
+                    if let Some(Events::ShutdownMessage) = x {
+                        break;
+                    }
+                },
+                _ = interval.next() => {
+                    let now_ms = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|x| x.as_millis() as u64)
+                        .unwrap_or(0);
+                    self.peers.write().unwrap().expire(now_ms);
+                    self.write(&log);
+                    self.check_divergence(&mut reported, &log);
+                },
+            }
+        }
+        self.write(&log);
+        info!(log, "Peer table closed"; "peers" => self.peers.read().unwrap().len());
+    }
+
+    fn check_divergence(&self, reported: &mut HashSet<(String, u64)>, log: &slog::Logger) {
+        let groups = self.peers.read().unwrap().checkpoints();
+        let divergences = divergences(&groups);
+        metrics::FINALIZED_DIVERGENCES.set(divergences.len() as i64);
+        for divergence in divergences {
+            if !reported.insert((divergence.fork_digest.clone(), divergence.finalized_epoch)) {
+                continue;
+            }
+            let sides: Vec<String> = divergence
+                .groups
+                .iter()
+                .map(|group| format!("{} ({} peers: {:?})", group.finalized_root, group.peers, group.clients))
+                .collect();
+            warn!(
+                log,
+                "Peers disagree on the finalized checkpoint";
+                "fork_digest" => &divergence.fork_digest,
+                "epoch" => divergence.finalized_epoch,
+                "roots" => sides.join(", ")
+            );
+        }
+    }
+
+    fn write(&self, log: &slog::Logger) {
+        if self.output_format.is_streaming() {
+            return;
+        }
+        let records: Vec<PeerRecord> = self
+            .peers
+            .read()
+            .unwrap()
+            .peers(None)
+            .into_iter()
+            .cloned()
+            .collect();
+        if let Err(e) = write_snapshot(&records, self.output_format, &self.dir) {
+            warn!(log, "Failed to write peer snapshot: {}", e);
+        }
+    }
+}
+
+fn write_snapshot(records: &[PeerRecord], output_format: OutputFormat, dir: &Path) -> std::io::Result<()> {
+    if output_format == OutputFormat::Parquet {
+        // each snapshot replaces the previous file, so it is never rolled over
+        let mut sink = ParquetSink::create(dir, SNAPSHOT_NAME, Rotation::default())?;
+        for record in records {
+            sink.write(record.clone())?;
+        }
+        return sink.close();
+    }
+    let mut sink = Sink::create(output_format, dir, SNAPSHOT_NAME, true)?;
+    for record in records {
+        sink.write(record)?;
+    }
+    sink.flush()
+}
+
+impl ParquetRecord for PeerRecord {
+    fn schema() -> SchemaRef {
+        let timestamp = |name| Field::new(name, DataType::Timestamp(TimeUnit::Millisecond, None), true);
+        Arc::new(Schema::new(vec![
+            Field::new("peer_id", DataType::Utf8, false),
+            Field::new("agent_string", DataType::Utf8, false),
+            Field::new("connected", DataType::Boolean, false),
+            timestamp("connected_at"),
+            timestamp("last_seen"),
+            Field::new("fork_digest", DataType::Utf8, true),
+            Field::new("head_slot", DataType::UInt64, true),
+            Field::new("head_root", DataType::Utf8, true),
+            Field::new("finalized_epoch", DataType::UInt64, true),
+            Field::new("finalized_root", DataType::Utf8, true),
+            Field::new("metadata_seq_number", DataType::UInt64, true),
+            Field::new(
+                "attnets",
+                DataType::List(Box::new(Field::new("item", DataType::UInt64, true))),
+                false,
+            ),
+            Field::new("gossip_messages", DataType::UInt64, false),
+            Field::new("gossip_blocks", DataType::UInt64, false),
+            Field::new("gossip_aggregates", DataType::UInt64, false),
+            Field::new("gossip_attestations", DataType::UInt64, false),
+            Field::new("connections", DataType::UInt64, false),
+            Field::new("goodbyes", DataType::UInt64, false),
+            Field::new("last_goodbye", DataType::Utf8, true),
+            Field::new("disconnects", DataType::UInt64, false),
+            Field::new("dial_failures", DataType::UInt64, false),
+        ]))
+    }
+
+    fn to_record_batch(records: &[Self]) -> ArrowResult<RecordBatch> {
+        let n = records.len();
+        let mut peer_id = StringBuilder::new(n);
+        let mut agent_string = StringBuilder::new(n);
+        let mut connected = BooleanBuilder::new(n);
+        let mut connected_at = TimestampMillisecondBuilder::new(n);
+        let mut last_seen = TimestampMillisecondBuilder::new(n);
+        let mut fork_digest = StringBuilder::new(n);
+        let mut head_slot = UInt64Builder::new(n);
+        let mut head_root = StringBuilder::new(n);
+        let mut finalized_epoch = UInt64Builder::new(n);
+        let mut finalized_root = StringBuilder::new(n);
+        let mut metadata_seq_number = UInt64Builder::new(n);
+        let mut attnets = ListBuilder::new(UInt64Builder::new(n));
+        let mut gossip_messages = UInt64Builder::new(n);
+        let mut gossip_blocks = UInt64Builder::new(n);
+        let mut gossip_aggregates = UInt64Builder::new(n);
+        let mut gossip_attestations = UInt64Builder::new(n);
+        let mut connections = UInt64Builder::new(n);
+        let mut goodbyes = UInt64Builder::new(n);
+        let mut last_goodbye = StringBuilder::new(n);
+        let mut disconnects = UInt64Builder::new(n);
+        let mut dial_failures = UInt64Builder::new(n);
+
+        fn append_option(builder: &mut StringBuilder, value: &Option<String>) -> ArrowResult<()> {
+            match value {
+                Some(value) => builder.append_value(value),
+                None => builder.append_null(),
+            }
+        }
+
+        for record in records {
+            peer_id.append_value(&record.peer_id)?;
+            agent_string.append_value(&record.agent_string)?;
+            connected.append_value(record.connected)?;
+            connected_at.append_option(record.connected_at.map(|x| x as i64))?;
+            last_seen.append_value(record.last_seen as i64)?;
+            append_option(&mut fork_digest, &record.fork_digest)?;
+            head_slot.append_option(record.head_slot)?;
+            append_option(&mut head_root, &record.head_root)?;
+            finalized_epoch.append_option(record.finalized_epoch)?;
+            append_option(&mut finalized_root, &record.finalized_root)?;
+            metadata_seq_number.append_option(record.metadata_seq_number)?;
+            for subnet in &record.subnets {
+                attnets.values().append_value(*subnet)?;
+            }
+            attnets.append(true)?;
+            gossip_messages.append_value(record.gossip_messages)?;
+            gossip_blocks.append_value(record.gossip_blocks)?;
+            gossip_aggregates.append_value(record.gossip_aggregates)?;
+            gossip_attestations.append_value(record.gossip_attestations)?;
+            connections.append_value(record.connections)?;
+            goodbyes.append_value(record.goodbyes)?;
+            match record.last_goodbye {
+                Some(reason) => last_goodbye.append_value(reason)?,
+                None => last_goodbye.append_null()?,
+            }
+            disconnects.append_value(record.disconnects)?;
+            dial_failures.append_value(record.dial_failures)?;
+        }
+        RecordBatch::try_new(
+            Self::schema(),
+            vec![
+                Arc::new(peer_id.finish()) as ArrayRef,
+                Arc::new(agent_string.finish()),
+                Arc::new(connected.finish()),
+                Arc::new(connected_at.finish()),
+                Arc::new(last_seen.finish()),
+                Arc::new(fork_digest.finish()),
+                Arc::new(head_slot.finish()),
+                Arc::new(head_root.finish()),
+                Arc::new(finalized_epoch.finish()),
+                Arc::new(finalized_root.finish()),
+                Arc::new(metadata_seq_number.finish()),
+                Arc::new(attnets.finish()),
+                Arc::new(gossip_messages.finish()),
+                Arc::new(gossip_blocks.finish()),
+                Arc::new(gossip_aggregates.finish()),
+                Arc::new(gossip_attestations.finish()),
+                Arc::new(connections.finish()),
+                Arc::new(goodbyes.finish()),
+                Arc::new(last_goodbye.finish()),
+                Arc::new(disconnects.finish()),
+                Arc::new(dial_failures.finish()),
+            ],
+        )
+    }
+}
@@ -0,0 +1,128 @@
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+/// Number of message ids remembered before the oldest are evicted.
+const SEEN_CACHE_CAPACITY: usize = 65536;
+
+/// How a single gossip message spread to us.
+#[derive(Serialize, Clone, Debug)]
+pub struct SeenMessage {
+    pub message_id: String,
+    pub topic: String,
+    pub first_peer_id: String,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    pub deliveries: u64,
+}
+
+impl SeenMessage {
+    /// Milliseconds between the first and the latest delivery.
+    pub fn spread_ms(&self) -> u64 {
+        self.last_seen_ms - self.first_seen_ms
+    }
+}
+
+/// A peer ranked by how many messages it delivered before anyone else.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FirstDeliverer {
+    pub peer_id: String,
+    pub agent_string: String,
+    pub first_deliveries: u64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PropagationStats {
+    pub messages: usize,
+    pub deliveries: u64,
+    pub duplicates: u64,
+}
+
+/// Seen-cache of recent gossip message ids.
+///
+/// Tracks every delivery of a message id, including duplicates relayed by
+/// other peers, so we know who delivered it first and how long it took to
+/// reach us through the slowest peer.
+#[derive(Default)]
+pub struct Propagation {
+    messages: HashMap<String, SeenMessage>,
+    order: VecDeque<String>,
+    first_deliveries: HashMap<String, u64>,
+    peer_agents: HashMap<String, String>,
+    deliveries: u64,
+    duplicates: u64,
+}
+
+pub type SharedPropagation = Arc<RwLock<Propagation>>;
+
+impl Propagation {
+    /// Records a delivery and returns the updated state of the message.
+    pub fn observe(
+        &mut self,
+        message_id: &str,
+        topic: &str,
+        peer_id: &str,
+        agent_string: &str,
+        arrival_ms: u64,
+    ) -> SeenMessage {
+        self.deliveries += 1;
+        if let Some(message) = self.messages.get_mut(message_id) {
+            self.duplicates += 1;
+            message.deliveries += 1;
+            message.last_seen_ms = message.last_seen_ms.max(arrival_ms);
+            return message.clone();
+        }
+
+        if self.order.len() >= SEEN_CACHE_CAPACITY {
+            if let Some(evicted) = self.order.pop_front() {
+                self.messages.remove(&evicted);
+            }
+        }
+        *self.first_deliveries.entry(peer_id.to_string()).or_insert(0) += 1;
+        self.peer_agents
+            .insert(peer_id.to_string(), agent_string.to_string());
+        let message = SeenMessage {
+            message_id: message_id.to_string(),
+            topic: topic.to_string(),
+            first_peer_id: peer_id.to_string(),
+            first_seen_ms: arrival_ms,
+            last_seen_ms: arrival_ms,
+            deliveries: 1,
+        };
+        self.order.push_back(message_id.to_string());
+        self.messages.insert(message_id.to_string(), message.clone());
+        message
+    }
+
+    pub fn message(&self, message_id: &str) -> Option<&SeenMessage> {
+        self.messages.get(message_id)
+    }
+
+    /// Returns the peers that delivered the most messages first, best first.
+    pub fn first_deliverers(&self, limit: usize) -> Vec<FirstDeliverer> {
+        let mut ranking: Vec<FirstDeliverer> = self
+            .first_deliveries
+            .iter()
+            .map(|(peer_id, first_deliveries)| FirstDeliverer {
+                peer_id: peer_id.clone(),
+                agent_string: self.peer_agents.get(peer_id).cloned().unwrap_or_default(),
+                first_deliveries: *first_deliveries,
+            })
+            .collect();
+        ranking.sort_by(|a, b| {
+            b.first_deliveries
+                .cmp(&a.first_deliveries)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        ranking.truncate(limit);
+        ranking
+    }
+
+    pub fn stats(&self) -> PropagationStats {
+        PropagationStats {
+            messages: self.messages.len(),
+            deliveries: self.deliveries,
+            duplicates: self.duplicates,
+        }
+    }
+}
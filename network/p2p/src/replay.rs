@@ -0,0 +1,181 @@
+use crate::adapter::Client;
+use crate::output::OutputFormat;
+use crate::participation::SharedParticipation;
+use crate::payload_archive::{load_payload, read_index};
+use crate::peers::SharedPeerTable;
+use crate::propagation::SharedPropagation;
+use crate::rotation::Rotation;
+use crate::slots::{SharedSlotStats, SlotStats};
+use clap::{App, Arg, ArgMatches};
+use eth2::testnet::presets::get_genesis_time;
+use eth2::utils::{get_chain_spec, get_genesis_time_from_dir};
+use slog::{info, warn};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::watch;
+use types::events::Events;
+
+/// Entries replayed between two yields to the runtime in fast mode, so the
+/// replay shares its worker and notices a shutdown.
+const YIELD_INTERVAL: u64 = 256;
+
+/// Feeds an archive written with `--payload-archive` through the same
+/// decoding path as live gossip.
+pub struct Replay {
+    archive_dir: PathBuf,
+    output_dir: PathBuf,
+    output_format: OutputFormat,
+    rotation: Rotation,
+    original_timing: bool,
+    genesis_time: Option<u64>,
+}
+
+impl Replay {
+    /// Fails if `testnet_dir` cannot be loaded.
+    pub fn new(arg_matches: &ArgMatches<'_>, testnet_dir: Option<PathBuf>) -> Result<Self, String> {
+        let replay_arg_matches = arg_matches.subcommand_matches("replay").unwrap();
+        let archive_dir = PathBuf::from(
+            replay_arg_matches
+                .value_of("archive")
+                .expect("required parameter"),
+        );
+        let output_dir = replay_arg_matches
+            .value_of("output-dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| archive_dir.join("replay"));
+        let output_format = arg_matches
+            .value_of("output-format")
+            .unwrap_or("csv")
+            .parse::<OutputFormat>()
+            .expect("Invalid output format");
+        Ok(Replay {
+            archive_dir,
+            output_dir,
+            output_format,
+            rotation: Rotation::from_arg_matches(arg_matches)?,
+            original_timing: replay_arg_matches.value_of("timing") == Some("original"),
+            genesis_time: get_genesis_time_from_dir(testnet_dir)?,
+        })
+    }
+
+    /// Replays every archived arrival and returns the process exit code:
+    /// 0 if messages were replayed, 1 if the archive could not be read and
+    /// 2 if it was empty.
+    pub async fn run(self, shutdown_rx: watch::Receiver<Events>, log: slog::Logger) -> i32 {
+        let entries = match read_index(&self.archive_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(log, "Failed to read the index of {:?}: {}", self.archive_dir, e);
+                return 1;
+            }
+        };
+        info!(log, "Replaying {} gossip messages from {:?}", entries.len(), self.archive_dir);
+
+        // the fork digest is the second segment of every eth2 topic
+        let genesis_time = self.genesis_time.or_else(|| {
+            entries
+                .first()
+                .and_then(|entry| entry.topic.split('/').nth(2))
+                .and_then(get_genesis_time)
+        });
+        let (client, writers) = Client::new(
+            &self.output_dir,
+            self.output_format,
+            self.rotation,
+            None,
+            genesis_time,
+            SharedPropagation::default(),
+            None,
+            SharedPeerTable::default(),
+            None,
+            SharedSlotStats::new(RwLock::new(SlotStats::new(
+                get_chain_spec().milliseconds_per_slot / 3,
+            ))),
+            SharedParticipation::default(),
+            log.clone(),
+        );
+
+        let mut replayed = 0;
+        let mut missing = 0;
+        let mut previous_arrival_ms = None;
+        for (i, entry) in (0u64..).zip(entries) {
+            if !self.original_timing && i > 0 && i % YIELD_INTERVAL == 0 {
+                tokio::task::yield_now().await;
+            }
+            if let Events::ShutdownMessage = *shutdown_rx.borrow() {
+                info!(log, "Replay interrupted by shutdown");
+                break;
+            }
+            if self.original_timing {
+                if let Some(previous_arrival_ms) = previous_arrival_ms {
+                    let gap = entry.arrival_ms.saturating_sub(previous_arrival_ms);
+                    tokio::time::delay_for(Duration::from_millis(gap)).await;
+                }
+                previous_arrival_ms = Some(entry.arrival_ms);
+            }
+            let data = match load_payload(&self.archive_dir, &entry) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!(log, "Missing payload {}: {}", entry.payload, e);
+                    missing += 1;
+                    continue;
+                }
+            };
+            client.handle_gossip(
+                Duration::from_millis(entry.arrival_ms),
+                entry.message_id,
+                entry.sequence_number,
+                entry.agent_string,
+                entry.peer_id,
+                entry.topic,
+                data,
+            );
+            replayed += 1;
+        }
+        drop(client);
+        // waits for the writer threads to flush
+        let _ = tokio::task::spawn_blocking(move || writers.close()).await;
+        info!(
+            log,
+            "Replay finished";
+            "replayed" => replayed,
+            "missing_payloads" => missing,
+            "output_dir" => format!("{:?}", self.output_dir)
+        );
+        if replayed > 0 {
+            0
+        } else {
+            2
+        }
+    }
+}
+
+pub fn cli_app<'a, 'b>() -> App<'a, 'b> {
+    App::new("replay")
+        .version(clap::crate_version!())
+        .about("Replays archived gossip payloads through the decoder.")
+        .arg(
+            Arg::with_name("archive")
+                .value_name("ARCHIVE")
+                .help("Directory written with --payload-archive.")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("timing")
+                .long("timing")
+                .value_name("TIMING")
+                .help("Keep the original gaps between arrivals or replay as fast as possible.")
+                .takes_value(true)
+                .possible_values(&["original", "fast"])
+                .default_value("fast"),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .help("Where replayed records are written. Defaults to <ARCHIVE>/replay.")
+                .takes_value(true),
+        )
+}
@@ -0,0 +1,224 @@
+use chrono::Local;
+use clap::ArgMatches;
+use flate2::write::GzEncoder;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Name of the directory, next to the live output files, that holds rotated files.
+pub const ARCHIVE_DIR: &str = "archive";
+
+pub const COMPRESSION_TYPES: &[&str] = &["none", "gzip", "zstd"];
+
+/// Limits after which an output file is closed and a new one is started.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rollover {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl Rollover {
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age.is_some()
+    }
+
+    pub fn is_due(&self, bytes: u64, age: Duration) -> bool {
+        self.max_bytes.map_or(false, |max| bytes >= max)
+            || self.max_age.map_or(false, |max| age >= max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression type: {}", s)),
+        }
+    }
+}
+
+/// How long rotated files are kept. Oldest files are removed first.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Retention {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+/// Rotation, compression and retention settings shared by all output files.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rotation {
+    pub rollover: Rollover,
+    pub compression: Compression,
+    pub retention: Retention,
+}
+
+impl Rotation {
+    /// Reads the `--rotate-*` and `--retention-*` options. Every limit must
+    /// be at least 1 and fit into 64 bits once converted to bytes or seconds.
+    pub fn from_arg_matches(arg_matches: &ArgMatches<'_>) -> Result<Self, String> {
+        // `name` in units of `unit`, converted to bytes or seconds
+        let parse = |name: &str, unit: u64| -> Result<Option<u64>, String> {
+            match arg_matches.value_of(name) {
+                Some(value) => match value.parse::<u64>() {
+                    Ok(x) if x > 0 => x
+                        .checked_mul(unit)
+                        .map(Some)
+                        .ok_or_else(|| format!("--{} {} is too large", name, value)),
+                    _ => Err(format!("--{} must be a whole number of at least 1, got {:?}", name, value)),
+                },
+                None => Ok(None),
+            }
+        };
+        Ok(Rotation {
+            rollover: Rollover {
+                max_bytes: parse("rotate-size", 1024 * 1024)?,
+                max_age: parse("rotate-interval", 1)?.map(Duration::from_secs),
+            },
+            compression: arg_matches
+                .value_of("rotate-compression")
+                .unwrap_or("none")
+                .parse::<Compression>()?,
+            retention: Retention {
+                max_age: parse("retention-days", 86400)?.map(Duration::from_secs),
+                max_bytes: parse("retention-size", 1024 * 1024 * 1024)?,
+            },
+        })
+    }
+
+    /// Moves `path` into the archive directory with a millisecond timestamp
+    /// suffix, compresses it and applies the retention policy. Missing and
+    /// empty files are left alone.
+    pub fn archive(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.len() > 0 => (),
+            _ => return Ok(None),
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let archive_dir = dir.join(ARCHIVE_DIR);
+        fs::create_dir_all(&archive_dir)?;
+
+        // parquet files are already compressed internally
+        let is_parquet = path.extension().map_or(false, |x| x == "parquet");
+        let extension = self.compression.extension().filter(|_| !is_parquet);
+        let suffix = Local::now().format("%Y%m%d%H%M%S%3f").to_string();
+        let mut target = archive_dir.join(rotated_file_name(path, &suffix));
+        // rotations within the same millisecond get a counter
+        let mut counter = 0;
+        while target.exists() || extension.map_or(false, |x| with_suffix(&target, x).exists()) {
+            counter += 1;
+            target = archive_dir.join(rotated_file_name(path, &format!("{}-{}", suffix, counter)));
+        }
+        fs::rename(path, &target)?;
+
+        let target = match extension {
+            Some(extension) => {
+                let compressed = with_suffix(&target, extension);
+                compress(&target, &compressed, self.compression)?;
+                fs::remove_file(&target)?;
+                compressed
+            }
+            None => target,
+        };
+
+        self.prune(&archive_dir)?;
+        Ok(Some(target))
+    }
+
+    /// Removes archived files that exceed the retention policy, oldest first.
+    pub fn prune(&self, archive_dir: &Path) -> io::Result<()> {
+        let retention = self.retention;
+        if retention.max_age.is_none() && retention.max_bytes.is_none() {
+            return Ok(());
+        }
+        let mut files = vec![];
+        for entry in fs::read_dir(archive_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push((entry.path(), modified, metadata.len()));
+            }
+        }
+        files.sort_by_key(|(_, modified, _)| *modified);
+
+        let mut total_bytes: u64 = files.iter().map(|(_, _, len)| len).sum();
+        let now = SystemTime::now();
+        for (path, modified, len) in files {
+            let expired = match retention.max_age {
+                Some(max_age) => now.duration_since(modified).unwrap_or_default() > max_age,
+                None => false,
+            };
+            let over_size = retention.max_bytes.map_or(false, |max| total_bytes > max);
+            if !expired && !over_size {
+                break;
+            }
+            fs::remove_file(&path)?;
+            total_bytes -= len;
+        }
+        Ok(())
+    }
+}
+
+/// `<dir>/gossip.csv` rotated at `suffix` becomes `gossip-<suffix>.csv`.
+pub fn rotated_file_name(path: &Path, suffix: &str) -> String {
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    }
+}
+
+/// `gossip-<suffix>.csv` with `gz` becomes `gossip-<suffix>.csv.gz`.
+fn with_suffix(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+fn compress(source: &Path, target: &Path, compression: Compression) -> io::Result<()> {
+    let mut input = File::open(source)?;
+    let output = File::create(target)?;
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+        }
+        Compression::Zstd => zstd::stream::copy_encode(input, output, 0)?,
+        Compression::None => (),
+    }
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use crate::columnar::ParquetRecord;
+use crate::metrics;
+use arrow::array::{ArrayRef, Int64Builder, StringBuilder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::Result as ArrowResult;
+use arrow::record_batch::RecordBatch;
+use eth2::types::{EthSpec, Hash256, MainnetEthSpec};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+/// Slots a block may trail the newest block before its slot is settled.
+const SLOT_LAG: u64 = 1;
+/// Longest gap settled at once. Larger jumps, e.g. after imp lost its
+/// peers for a while, restart the series instead of reporting every slot
+/// in between as missed.
+const MAX_SETTLE_SLOTS: u64 = 1024;
+/// Settled epochs kept for the api.
+const RECENT_EPOCHS: usize = 256;
+
+/// Outcome of a settled slot.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SlotRecord {
+    pub slot: u64,
+    pub epoch: u64,
+    /// `proposed` or `missed`.
+    pub outcome: &'static str,
+    pub proposer_index: Option<u64>,
+    pub block_root: Option<String>,
+    /// Distinct blocks seen for the slot.
+    pub blocks: u64,
+    /// Arrival of the first block relative to the start of the slot.
+    pub arrival_offset_ms: Option<i64>,
+    /// `early`, `on_time` or `late`, if the genesis time is known.
+    pub timeliness: Option<&'static str>,
+}
+
+/// Slot outcomes of an epoch. The first epoch after imp started only
+/// counts the slots it observed.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct EpochRecord {
+    pub epoch: u64,
+    pub slots: u64,
+    pub proposed: u64,
+    pub missed: u64,
+    pub early: u64,
+    pub late: u64,
+}
+
+/// Blocks of one proposer seen by imp.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ProposerStats {
+    pub proposer_index: u64,
+    pub blocks: u64,
+    pub late_blocks: u64,
+    pub last_slot: u64,
+}
+
+struct PendingSlot {
+    proposer_index: u64,
+    root: Hash256,
+    arrival_offset_ms: Option<i64>,
+    blocks: u64,
+}
+
+/// Settles slots as proposed or missed from the blocks imp sees.
+///
+/// A slot is settled once a block more than `SLOT_LAG` slots newer arrives,
+/// so missed slots are only reported when the chain moves on. Blocks for an
+/// already settled slot still count for their proposer, but do not change
+/// the slot's outcome.
+pub struct SlotStats {
+    late_threshold_ms: i64,
+    pending: BTreeMap<u64, PendingSlot>,
+    next_slot: Option<u64>,
+    epoch: Option<EpochRecord>,
+    recent_epochs: VecDeque<EpochRecord>,
+    proposers: HashMap<u64, ProposerStats>,
+}
+
+pub type SharedSlotStats = Arc<RwLock<SlotStats>>;
+
+impl SlotStats {
+    /// Blocks arriving more than `late_threshold_ms` into their slot are
+    /// late.
+    pub fn new(late_threshold_ms: u64) -> Self {
+        SlotStats {
+            late_threshold_ms: late_threshold_ms as i64,
+            pending: BTreeMap::new(),
+            next_slot: None,
+            epoch: None,
+            recent_epochs: VecDeque::new(),
+            proposers: HashMap::new(),
+        }
+    }
+
+    /// Records a block and returns the slots and epochs it settled.
+    pub fn on_block(
+        &mut self,
+        slot: u64,
+        root: Hash256,
+        proposer_index: u64,
+        arrival_offset_ms: Option<i64>,
+    ) -> (Vec<SlotRecord>, Vec<EpochRecord>) {
+        // a block far behind the series means an earlier one claimed a bogus
+        // slot, so the series restarts instead of ignoring every block
+        let behind = self
+            .next_slot
+            .map_or(false, |next_slot| slot.saturating_add(MAX_SETTLE_SLOTS) < next_slot);
+        if behind {
+            self.next_slot = None;
+            self.epoch = None;
+            self.pending.clear();
+        }
+        let next_slot = *self.next_slot.get_or_insert(slot);
+        if slot >= next_slot {
+            match self.pending.get_mut(&slot) {
+                Some(pending) => pending.blocks += 1,
+                None => {
+                    self.pending.insert(
+                        slot,
+                        PendingSlot {
+                            proposer_index,
+                            root,
+                            arrival_offset_ms,
+                            blocks: 1,
+                        },
+                    );
+                }
+            }
+        }
+        let late = self.timeliness(arrival_offset_ms) == Some("late");
+        let proposer = self
+            .proposers
+            .entry(proposer_index)
+            .or_insert_with(|| ProposerStats {
+                proposer_index,
+                ..Default::default()
+            });
+        proposer.blocks += 1;
+        proposer.late_blocks += late as u64;
+        proposer.last_slot = proposer.last_slot.max(slot);
+        self.settle(slot.saturating_sub(SLOT_LAG))
+    }
+
+    fn timeliness(&self, arrival_offset_ms: Option<i64>) -> Option<&'static str> {
+        arrival_offset_ms.map(|offset| {
+            if offset < 0 {
+                "early"
+            } else if offset > self.late_threshold_ms {
+                "late"
+            } else {
+                "on_time"
+            }
+        })
+    }
+
+    /// Settles every slot before `until`.
+    fn settle(&mut self, until: u64) -> (Vec<SlotRecord>, Vec<EpochRecord>) {
+        let slots_per_epoch = MainnetEthSpec::slots_per_epoch();
+        let mut slots = vec![];
+        let mut epochs = vec![];
+        let mut next_slot = self.next_slot.unwrap_or(until);
+        if until > next_slot.saturating_add(MAX_SETTLE_SLOTS) {
+            next_slot = until;
+            self.epoch = None;
+            self.pending = self.pending.split_off(&until);
+        }
+        while next_slot < until {
+            let epoch_number = next_slot / slots_per_epoch;
+            let record = match self.pending.remove(&next_slot) {
+                Some(pending) => SlotRecord {
+                    slot: next_slot,
+                    epoch: epoch_number,
+                    outcome: "proposed",
+                    proposer_index: Some(pending.proposer_index),
+                    block_root: Some(format!("{:?}", pending.root)),
+                    blocks: pending.blocks,
+                    arrival_offset_ms: pending.arrival_offset_ms,
+                    timeliness: self.timeliness(pending.arrival_offset_ms),
+                },
+                None => SlotRecord {
+                    slot: next_slot,
+                    epoch: epoch_number,
+                    outcome: "missed",
+                    ..Default::default()
+                },
+            };
+            metrics::SLOTS.with_label_values(&[record.outcome]).inc();
+            if let Some(timeliness) = record.timeliness {
+                metrics::BLOCK_TIMELINESS.with_label_values(&[timeliness]).inc();
+            }
+            let epoch = self.epoch.get_or_insert_with(|| EpochRecord {
+                epoch: epoch_number,
+                ..Default::default()
+            });
+            epoch.slots += 1;
+            match record.outcome {
+                "proposed" => epoch.proposed += 1,
+                _ => epoch.missed += 1,
+            }
+            match record.timeliness {
+                Some("early") => epoch.early += 1,
+                Some("late") => epoch.late += 1,
+                _ => {}
+            }
+            slots.push(record);
+            next_slot += 1;
+            if next_slot % slots_per_epoch == 0 {
+                if let Some(epoch) = self.epoch.take() {
+                    self.recent_epochs.push_back(epoch.clone());
+                    if self.recent_epochs.len() > RECENT_EPOCHS {
+                        self.recent_epochs.pop_front();
+                    }
+                    epochs.push(epoch);
+                }
+            }
+        }
+        self.next_slot = Some(next_slot);
+        (slots, epochs)
+    }
+
+    /// The most recently settled epochs, newest first.
+    pub fn epochs(&self, limit: usize) -> Vec<&EpochRecord> {
+        self.recent_epochs.iter().rev().take(limit).collect()
+    }
+
+    /// Proposers ordered by the number of blocks seen from them.
+    pub fn proposers(&self, limit: usize) -> Vec<&ProposerStats> {
+        let mut proposers: Vec<&ProposerStats> = self.proposers.values().collect();
+        proposers.sort_by(|a, b| {
+            b.blocks
+                .cmp(&a.blocks)
+                .then(a.proposer_index.cmp(&b.proposer_index))
+        });
+        proposers.truncate(limit);
+        proposers
+    }
+}
+
+impl ParquetRecord for SlotRecord {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("slot", DataType::UInt64, false),
+            Field::new("epoch", DataType::UInt64, false),
+            Field::new("outcome", DataType::Utf8, false),
+            Field::new("proposer_index", DataType::UInt64, true),
+            Field::new("block_root", DataType::Utf8, true),
+            Field::new("blocks", DataType::UInt64, false),
+            Field::new("arrival_offset_ms", DataType::Int64, true),
+            Field::new("timeliness", DataType::Utf8, true),
+        ]))
+    }
+
+    fn to_record_batch(records: &[Self]) -> ArrowResult<RecordBatch> {
+        let n = records.len();
+        let mut slot = UInt64Builder::new(n);
+        let mut epoch = UInt64Builder::new(n);
+        let mut outcome = StringBuilder::new(n);
+        let mut proposer_index = UInt64Builder::new(n);
+        let mut block_root = StringBuilder::new(n);
+        let mut blocks = UInt64Builder::new(n);
+        let mut arrival_offset_ms = Int64Builder::new(n);
+        let mut timeliness = StringBuilder::new(n);
+        for record in records {
+            slot.append_value(record.slot)?;
+            epoch.append_value(record.epoch)?;
+            outcome.append_value(record.outcome)?;
+            proposer_index.append_option(record.proposer_index)?;
+            match &record.block_root {
+                Some(root) => block_root.append_value(root)?,
+                None => block_root.append_null()?,
+            }
+            blocks.append_value(record.blocks)?;
+            arrival_offset_ms.append_option(record.arrival_offset_ms)?;
+            match record.timeliness {
+                Some(x) => timeliness.append_value(x)?,
+                None => timeliness.append_null()?,
+            }
+        }
+        RecordBatch::try_new(
+            Self::schema(),
+            vec![
+                Arc::new(slot.finish()) as ArrayRef,
+                Arc::new(epoch.finish()),
+                Arc::new(outcome.finish()),
+                Arc::new(proposer_index.finish()),
+                Arc::new(block_root.finish()),
+                Arc::new(blocks.finish()),
+                Arc::new(arrival_offset_ms.finish()),
+                Arc::new(timeliness.finish()),
+            ],
+        )
+    }
+}
+
+impl ParquetRecord for EpochRecord {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("epoch", DataType::UInt64, false),
+            Field::new("slots", DataType::UInt64, false),
+            Field::new("proposed", DataType::UInt64, false),
+            Field::new("missed", DataType::UInt64, false),
+            Field::new("early", DataType::UInt64, false),
+            Field::new("late", DataType::UInt64, false),
+        ]))
+    }
+
+    fn to_record_batch(records: &[Self]) -> ArrowResult<RecordBatch> {
+        let column = |f: fn(&EpochRecord) -> u64| -> ArrowResult<ArrayRef> {
+            let mut builder = UInt64Builder::new(records.len());
+            for record in records {
+                builder.append_value(f(record))?;
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        };
+        RecordBatch::try_new(
+            Self::schema(),
+            vec![
+                column(|x| x.epoch)?,
+                column(|x| x.slots)?,
+                column(|x| x.proposed)?,
+                column(|x| x.missed)?,
+                column(|x| x.early)?,
+                column(|x| x.late)?,
+            ],
+        )
+    }
+}
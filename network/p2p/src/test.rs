@@ -1,6 +1,45 @@
 #[cfg(test)]
 mod test {
-    use super::adapter::pad_millis;
+    use super::adapter::{pad_millis, Client};
+    use crate::block_tree::BlockTree;
+    use crate::blocks::BlockStore;
+    use crate::chain::ChainState;
+    use crate::crawler::{self, Crawler, StopReason};
+    use crate::divergence::{checkpoint_groups, divergences};
+    use crate::forks::ForkIndex;
+    use crate::columnar::ParquetSink;
+    use crate::gossip::{is_supported_topic, slot_offset_ms, GossipRecord};
+    use crate::payload_archive::{load_payload, read_index, Arrival, PayloadArchive};
+    use crate::metadata::{parse_attnets, LocalMetaData};
+    use crate::message_id::{compute_message_ids, verify_message_id, MessageIdScheme};
+    use crate::output::OutputFormat;
+    use crate::participation::{Participation, SharedParticipation};
+    use crate::peers::{PeerRecord, PeerTable, SharedPeerTable};
+    use crate::propagation::{Propagation, SharedPropagation};
+    use crate::replay::Replay;
+    use crate::harness::{
+        crawler_args, free_port, line_count, replay_args, test_dir, test_logger, NodeSpec,
+        TestNetwork,
+    };
+    use crate::rotation::{rotated_file_name, Compression, Rollover, Rotation, ARCHIVE_DIR};
+    use crate::slots::SlotStats;
+    use crate::writer::{self, RecordSink};
+    use eth2::libp2p::rpc::methods::StatusMessage;
+    use eth2::ssz::Encode;
+    use eth2::types::{
+        BeaconBlock, EthSpec, Epoch, Hash256, MainnetEthSpec, Signature, SignedBeaconBlock, Slot,
+    };
+    use flate2::read::GzDecoder;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::io::{self, Read};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::time::{Duration, SystemTime};
+    use tokio::sync::watch;
+    use types::events::Events;
+
+    const MEDALLA: [u8; 4] = [0xe7, 0xa7, 0x5d, 0x5a];
+    const ALTONA: [u8; 4] = [0xfd, 0xca, 0x39, 0xb0];
     #[test]
     fn test_pad_millis() {
         assert_eq!(pad_millis(0), Some("000"));
@@ -13,4 +52,877 @@ mod test {
         assert_eq!(pad_millis(111), Some("111"));
         assert_eq!(pad_millis(1234), None);
     }
+
+    #[test]
+    fn test_fork_index() {
+        let mut fork_index = ForkIndex::new();
+        fork_index.insert("a", "f6775d07");
+        fork_index.insert("b", "f6775d07");
+        fork_index.insert("c", "e7a75d5a");
+        fork_index.insert("a", "f6775d07");
+        assert_eq!(fork_index.count("f6775d07"), 2);
+        assert_eq!(fork_index.num_nodes(), 3);
+
+        // a node that moves to a new fork is no longer counted on the old one
+        fork_index.insert("b", "e7a75d5a");
+        fork_index.insert("c", "");
+        assert_eq!(fork_index.count("f6775d07"), 1);
+        assert_eq!(fork_index.count("e7a75d5a"), 1);
+        assert_eq!(fork_index.count(""), 1);
+
+        let stats = fork_index.stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].fork_digest, "");
+        assert_eq!(stats[0].network, "none");
+        assert_eq!(stats[1].network, "medalla");
+        assert_eq!(stats[2].network, "witti");
+    }
+
+    #[test]
+    fn test_supported_topics() {
+        assert!(is_supported_topic("/eth2/e7a75d5a/beacon_block/ssz_snappy"));
+        assert!(is_supported_topic("/eth2/e7a75d5a/beacon_attestation_17/ssz_snappy"));
+        assert!(is_supported_topic("/eth2/e7a75d5a/attester_slashing/ssz_snappy"));
+        assert!(!is_supported_topic("/eth2/e7a75d5a/beacon_attestation_x/ssz_snappy"));
+        assert!(!is_supported_topic("/eth2/e7a75d5a/unknown/ssz_snappy"));
+    }
+
+    #[test]
+    fn test_slot_offset_ms() {
+        let genesis_time = 1596546008;
+        let slot_start_ms = (genesis_time + 10 * 12) * 1000;
+        assert_eq!(slot_offset_ms(genesis_time, 12000, 10, slot_start_ms + 1500), Some(1500));
+        assert_eq!(slot_offset_ms(genesis_time, 12000, 10, slot_start_ms - 250), Some(-250));
+        assert_eq!(slot_offset_ms(genesis_time, 12000, u64::max_value(), slot_start_ms), None);
+    }
+
+    #[test]
+    fn test_verify_message_id() {
+        let data = b"compressed";
+        let decompressed = b"decompressed";
+        let ids = compute_message_ids(data, Some(decompressed));
+        let legacy = base64::encode_config(&ids[0].1, base64::URL_SAFE_NO_PAD);
+        let v1 = hex::encode(&ids[1].1);
+        assert_eq!(ids[1].1.len(), 20);
+        assert_eq!(
+            verify_message_id(&legacy, data, Some(decompressed)),
+            Some(MessageIdScheme::Legacy)
+        );
+        assert_eq!(
+            verify_message_id(&v1, data, Some(decompressed)),
+            Some(MessageIdScheme::V1)
+        );
+        // the v1.0 id depends on whether the payload decompressed
+        assert_eq!(verify_message_id(&v1, data, None), None);
+        assert_eq!(verify_message_id("bogus", data, Some(decompressed)), None);
+    }
+
+    #[test]
+    fn test_payload_archive() {
+        let dir = test_dir("payload-archive");
+        let (archive, archive_handle) =
+            writer::spawn("payload_archive", PayloadArchive::open(&dir).unwrap(), test_logger());
+        let arrivals = vec![
+            (1000, "m1", "Lighthouse", "a", b"block"),
+            (1100, "m1", "Prysm", "b", b"block"),
+            (1200, "m2", "Prysm", "b", b"other"),
+        ];
+        for (arrival_ms, message_id, agent_string, peer_id, data) in arrivals {
+            archive.write(Arrival {
+                arrival_ms,
+                message_id: message_id.to_string(),
+                sequence_number: 1,
+                agent_string: agent_string.to_string(),
+                peer_id: peer_id.to_string(),
+                topic: "/eth2/e7a75d5a/beacon_block/ssz_snappy".to_string(),
+                data: data.to_vec(),
+            });
+        }
+        archive_handle.close();
+        let entries = read_index(&dir).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].payload, entries[1].payload);
+        assert_eq!(entries[1].peer_id, "b");
+        assert_eq!(load_payload(&dir, &entries[2]).unwrap(), b"other".to_vec());
+        // a truncated hash is an error, not a panic
+        let mut truncated = entries[2].clone();
+        truncated.payload.truncate(1);
+        let error = load_payload(&dir, &truncated).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // identical payloads are stored once
+        let payloads: usize = std::fs::read_dir(dir.join("payloads"))
+            .unwrap()
+            .map(|x| std::fs::read_dir(x.unwrap().path()).unwrap().count())
+            .sum();
+        assert_eq!(payloads, 2);
+    }
+
+    fn read_jsonl(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = test_dir("replay");
+        let archive_dir = dir.join("archive");
+        // replay takes the genesis time from the preset of the fork digest
+        let genesis_time = 1596546008;
+        let (client, writers) = Client::new(
+            &dir.join("live"),
+            OutputFormat::Jsonl,
+            Rotation::default(),
+            None,
+            Some(genesis_time),
+            SharedPropagation::default(),
+            Some(PayloadArchive::open(&archive_dir).unwrap()),
+            SharedPeerTable::default(),
+            None,
+            Arc::new(RwLock::new(SlotStats::new(4000))),
+            SharedParticipation::default(),
+            test_logger(),
+        );
+        let topic = "/eth2/e7a75d5a/beacon_block/ssz_snappy";
+        let arrivals = vec![("m1", 3, "a", 250), ("m1", 3, "b", 290), ("m2", 4, "b", 12100)];
+        for (message_id, slot, peer_id, offset_ms) in arrivals {
+            let data = snap::raw::Encoder::new()
+                .compress_vec(&test_block(slot, Hash256::zero()).as_ssz_bytes())
+                .unwrap();
+            client.handle_gossip(
+                Duration::from_millis(genesis_time * 1000 + 3 * 12000 + offset_ms),
+                message_id.to_string(),
+                1,
+                "Prysm".to_string(),
+                peer_id.to_string(),
+                topic.to_string(),
+                data,
+            );
+        }
+        drop(client);
+        writers.close();
+
+        let arg_matches = replay_args(&[
+            "imp",
+            "--output-format",
+            "jsonl",
+            "replay",
+            archive_dir.to_str().unwrap(),
+        ]);
+        let replay = Replay::new(&arg_matches, None).unwrap();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(Events::None);
+        assert_eq!(replay.run(shutdown_rx, test_logger()).await, 0);
+
+        let live = read_jsonl(&dir.join("live").join("gossip.jsonl"));
+        let replayed = read_jsonl(&archive_dir.join("replay").join("gossip.jsonl"));
+        assert_eq!(live.len(), 3);
+        assert_eq!(replayed, live);
+        assert_eq!(live[1]["deliveries"], 2);
+        assert_eq!(live[1]["first_peer_id"], "a");
+
+        // a testnet dir that cannot be loaded is an error, not a panic
+        assert!(Replay::new(&arg_matches, Some(dir.join("missing"))).is_err());
+
+        // a shutdown stops the replay before the next entry
+        let (_shutdown_tx, shutdown_rx) = watch::channel(Events::ShutdownMessage);
+        let replay = Replay::new(&arg_matches, None).unwrap();
+        assert_eq!(replay.run(shutdown_rx, test_logger()).await, 2);
+    }
+
+    #[derive(Clone, Default)]
+    struct MemorySink {
+        written: Arc<Mutex<Vec<u64>>>,
+        flushed: Arc<Mutex<usize>>,
+    }
+
+    impl RecordSink<u64> for MemorySink {
+        fn write(&mut self, record: u64) -> io::Result<()> {
+            self.written.lock().unwrap().push(record);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            *self.flushed.lock().unwrap() = self.written.lock().unwrap().len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_writer() {
+        let sink = MemorySink::default();
+        let (writer, handle) = writer::spawn("test", sink.clone(), test_logger());
+        let producers: Vec<_> = (0..4)
+            .map(|i| {
+                let writer = writer.clone();
+                std::thread::spawn(move || {
+                    for record in 0..1000 {
+                        writer.write(i * 1000 + record);
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        handle.close();
+        // every queued record is written and flushed on close
+        assert_eq!(sink.written.lock().unwrap().len(), 4000);
+        assert_eq!(*sink.flushed.lock().unwrap(), 4000);
+        // records written after close are dropped
+        writer.write(0);
+        assert_eq!(sink.written.lock().unwrap().len(), 4000);
+    }
+
+    #[test]
+    fn test_propagation() {
+        let mut propagation = Propagation::default();
+        propagation.observe("m1", "beacon_block", "a", "Lighthouse", 1000);
+        propagation.observe("m1", "beacon_block", "b", "Prysm", 1250);
+        let seen = propagation.observe("m1", "beacon_block", "c", "Teku", 1100);
+        assert_eq!(seen.first_peer_id, "a");
+        assert_eq!(seen.deliveries, 3);
+        assert_eq!(seen.spread_ms(), 250);
+        propagation.observe("m2", "beacon_block", "b", "Prysm", 2000);
+        propagation.observe("m3", "beacon_block", "b", "Prysm", 3000);
+
+        let ranking = propagation.first_deliverers(10);
+        assert_eq!(ranking.len(), 2);
+        assert_eq!(ranking[0].peer_id, "b");
+        assert_eq!(ranking[0].first_deliveries, 2);
+        assert_eq!(propagation.stats().duplicates, 2);
+    }
+
+    #[test]
+    fn test_chain_state() {
+        let mut chain = ChainState::new(MEDALLA);
+        chain.on_block(Hash256::repeat_byte(1), Slot::new(10));
+        chain.on_block(Hash256::repeat_byte(2), Slot::new(9));
+        assert_eq!(chain.head_root, Hash256::repeat_byte(1));
+
+        let mut status = StatusMessage {
+            fork_digest: MEDALLA,
+            finalized_root: Hash256::repeat_byte(3),
+            finalized_epoch: Epoch::new(1),
+            head_root: Hash256::repeat_byte(4),
+            head_slot: Slot::new(100),
+        };
+        // a head beyond the wall-clock slot is ignored, a plausible checkpoint is not
+        chain.on_status(&status, Some(Slot::new(50)));
+        assert_eq!(chain.head_slot, Slot::new(10));
+        assert_eq!(chain.finalized_epoch, Epoch::new(1));
+        chain.on_status(&status, Some(Slot::new(100)));
+        assert_eq!(chain.status(), status);
+
+        // a checkpoint beyond the wall-clock epoch is ignored
+        let mut future = status.clone();
+        future.finalized_root = Hash256::repeat_byte(5);
+        future.finalized_epoch = Epoch::new(4);
+        chain.on_status(&future, Some(Slot::new(100)));
+        assert_eq!(chain.finalized_epoch, Epoch::new(1));
+        assert_eq!(chain.finalized_root, Hash256::repeat_byte(3));
+        chain.on_status(&future, Some(Slot::new(128)));
+        assert_eq!(chain.finalized_epoch, Epoch::new(4));
+
+        status.fork_digest = ALTONA;
+        status.finalized_epoch = Epoch::new(5);
+        chain.on_status(&status, None);
+        assert_eq!(chain.finalized_epoch, Epoch::new(4));
+    }
+
+    fn test_block(slot: u64, parent_root: Hash256) -> SignedBeaconBlock<MainnetEthSpec> {
+        let mut message = BeaconBlock::empty(&MainnetEthSpec::default_spec());
+        message.slot = Slot::new(slot);
+        message.parent_root = parent_root;
+        SignedBeaconBlock {
+            message,
+            signature: Signature::empty_signature(),
+        }
+    }
+
+    #[test]
+    fn test_future_block() {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        // the wall clock is in slot 100
+        let genesis_time = now.as_secs() - 100 * 12 - 6;
+        let slot_stats = Arc::new(RwLock::new(SlotStats::new(4000)));
+        let (client, writers) = Client::new(
+            &test_dir("future-block"),
+            OutputFormat::Csv,
+            Rotation::default(),
+            None,
+            Some(genesis_time),
+            SharedPropagation::default(),
+            None,
+            SharedPeerTable::default(),
+            None,
+            slot_stats.clone(),
+            SharedParticipation::default(),
+            test_logger(),
+        );
+        let topic = "/eth2/e7a75d5a/beacon_block/ssz_snappy";
+        // m3 carries the block of m2 again, as a BlocksByRange response would
+        let blocks = vec![("m1", 1 << 40, 7), ("m2", 100, 5), ("m3", 100, 5)];
+        for (message_id, slot, proposer_index) in blocks {
+            let mut block = test_block(slot, Hash256::zero());
+            block.message.proposer_index = proposer_index;
+            let data = snap::raw::Encoder::new()
+                .compress_vec(&block.as_ssz_bytes())
+                .unwrap();
+            client.handle_gossip(
+                now,
+                message_id.to_string(),
+                0,
+                "Lighthouse".to_string(),
+                "a".to_string(),
+                topic.to_string(),
+                data,
+            );
+        }
+        drop(client);
+        writers.close();
+
+        // only the block of the current slot reached the slot statistics
+        let slot_stats = slot_stats.read().unwrap();
+        let proposers = slot_stats.proposers(10);
+        assert_eq!(proposers.len(), 1);
+        assert_eq!(proposers[0].proposer_index, 5);
+        assert_eq!(proposers[0].blocks, 1);
+    }
+
+    #[test]
+    fn test_duplicate_gossip() {
+        let dir = test_dir("duplicate-gossip");
+        let (client, writers) = Client::new(
+            &dir,
+            OutputFormat::Jsonl,
+            Rotation::default(),
+            None,
+            None,
+            SharedPropagation::default(),
+            None,
+            SharedPeerTable::default(),
+            None,
+            Arc::new(RwLock::new(SlotStats::new(4000))),
+            SharedParticipation::default(),
+            test_logger(),
+        );
+        let topic = "/eth2/e7a75d5a/beacon_block/ssz_snappy";
+        let block = snap::raw::Encoder::new()
+            .compress_vec(&test_block(7, Hash256::zero()).as_ssz_bytes())
+            .unwrap();
+        // the duplicate is not decoded, so its payload does not matter
+        let deliveries = vec![("a", 1000, block), ("b", 1030, b"garbage".to_vec())];
+        for (peer_id, arrival_ms, data) in deliveries {
+            client.handle_gossip(
+                Duration::from_millis(arrival_ms),
+                "m1".to_string(),
+                0,
+                "Lighthouse".to_string(),
+                peer_id.to_string(),
+                topic.to_string(),
+                data,
+            );
+        }
+        drop(client);
+        writers.close();
+
+        let records = read_jsonl(&dir.join("gossip.jsonl"));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["slot"], 7);
+        assert_eq!(records[1]["peer_id"], "b");
+        assert_eq!(records[1]["index"], 1);
+        assert_eq!(records[1]["deliveries"], 2);
+        assert_eq!(records[1]["spread_ms"], 30);
+        assert_eq!(records[1]["first_peer_id"], "a");
+        assert_eq!(records[1]["message_size"], records[0]["message_size"]);
+    }
+
+    #[test]
+    fn test_block_store() {
+        let mut store = BlockStore::new(3);
+        let a = test_block(1, Hash256::zero());
+        let b = test_block(2, a.canonical_root());
+        let c = test_block(3, b.canonical_root());
+        let d = test_block(3, a.canonical_root());
+        for block in vec![a.clone(), b.clone(), c.clone(), d.clone()] {
+            assert!(store.insert(block.canonical_root(), block));
+        }
+        // the oldest slot was dropped to stay within capacity
+        assert_eq!(store.len(), 3);
+        assert!(store.get(&a.canonical_root()).is_none());
+        assert!(!store.insert(b.canonical_root(), b.clone()));
+
+        let range = store.blocks_by_range(0, 10, 1, c.canonical_root());
+        assert_eq!(range, vec![&b, &c]);
+        let range = store.blocks_by_range(3, 1, 1, d.canonical_root());
+        assert_eq!(range, vec![&d]);
+        // blocks off the chain of the head are not served
+        assert!(store.blocks_by_range(2, 1, 1, d.canonical_root()).is_empty());
+        assert!(store.blocks_by_range(0, 10, 0, c.canonical_root()).is_empty());
+        assert_eq!(
+            store.blocks_by_root(&[d.canonical_root(), a.canonical_root()]),
+            vec![&d]
+        );
+    }
+
+    #[test]
+    fn test_local_meta_data() {
+        assert_eq!(parse_attnets("all").unwrap().len(), 64);
+        assert!(parse_attnets("none").unwrap().is_empty());
+        assert_eq!(parse_attnets("5, 0,5").unwrap(), vec![0, 5]);
+        assert!(parse_attnets("64").is_err());
+
+        let path = test_dir("metadata").join("metadata.json");
+        let first = LocalMetaData::load_or_update(&path, vec![0, 5]).unwrap();
+        assert_eq!(first.seq_number, 1);
+        let same = LocalMetaData::load_or_update(&path, vec![0, 5]).unwrap();
+        assert_eq!(same, first);
+        let changed = LocalMetaData::load_or_update(&path, vec![1]).unwrap();
+        assert_eq!(changed.seq_number, 2);
+        assert!(changed.meta_data().attnets.get(1).unwrap());
+        assert_eq!(changed.ping().data, 2);
+
+        // a corrupt file is an error rather than a reset of the sequence number
+        std::fs::write(&path, b"{").unwrap();
+        assert!(LocalMetaData::load_or_update(&path, vec![1]).is_err());
+    }
+
+    #[test]
+    fn test_peer_table() {
+        let mut peers = PeerTable::default();
+        peers.on_connected("b", 1000);
+        peers.on_gossip("b", "Prysm/v1.0.0", "/eth2/e7a75d5a/beacon_block/ssz_snappy", 2000);
+        peers.on_gossip("a", "Lighthouse/v0.2.0", "/eth2/e7a75d5a/beacon_attestation_3/ssz_snappy", 3000);
+        peers.on_status(
+            "b",
+            &StatusMessage {
+                fork_digest: MEDALLA,
+                finalized_root: Hash256::zero(),
+                finalized_epoch: Epoch::new(2),
+                head_root: Hash256::zero(),
+                head_slot: Slot::new(80),
+            },
+            4000,
+        );
+
+        let b = peers.peer("b").unwrap();
+        assert_eq!(b.connected_at, Some(1000));
+        assert_eq!(b.last_seen, 4000);
+        assert_eq!(b.gossip_blocks, 1);
+        assert_eq!(b.head_slot, Some(80));
+        assert_eq!(b.fork_digest.as_deref(), Some("e7a75d5a"));
+        assert_eq!(peers.peer("a").unwrap().gossip_attestations, 1);
+        let ids: Vec<&str> = peers.peers(None).iter().map(|x| x.peer_id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(peers.peers(Some("lighthouse")).len(), 1);
+
+        let goodbye = peers.on_goodbye("b", 129, 61000);
+        assert_eq!(goodbye.reason, Some("too_many_peers"));
+        assert_eq!(goodbye.duration_ms, Some(60000));
+        // a second Goodbye without a new connection has no duration
+        assert_eq!(peers.on_goodbye("b", 1, 62000).duration_ms, None);
+        // the connection already ended with the Goodbye
+        assert!(peers.on_disconnected("b", 63000).is_none());
+        let disconnect = peers.on_disconnected("a", 63000).unwrap();
+        assert_eq!((disconnect.event, disconnect.duration_ms), ("disconnected", None));
+        assert!(!peers.peer("a").unwrap().connected);
+        assert_eq!(peers.on_dial_failed("c", 64000).event, "dial_failed");
+        let stats = peers.connection_stats();
+        assert_eq!(stats["Prysm"].connections, 1);
+        assert_eq!(stats["Prysm"].goodbyes["too_many_peers"], 1);
+        assert_eq!(stats["Prysm"].mean_duration_ms, Some(60000));
+        assert_eq!(stats["Lighthouse"].connections, 0);
+        assert_eq!(stats["Lighthouse"].disconnects, 1);
+        assert_eq!(stats["unknown"].dial_failures, 1);
+
+        // disconnected peers are dropped after a while, their stats are kept
+        peers.on_connected("d", 64000);
+        peers.expire(64000 + 11 * 60 * 1000);
+        let ids: Vec<&str> = peers.peers(None).iter().map(|x| x.peer_id.as_str()).collect();
+        assert_eq!(ids, vec!["d"]);
+        assert_eq!(peers.connection_stats()["Prysm"].goodbyes["too_many_peers"], 1);
+        peers.expire(64000 + 61 * 60 * 1000);
+        assert!(peers.is_empty());
+    }
+
+    #[test]
+    fn test_divergences() {
+        let peer = |agent: &str, epoch: u64, root: &str| PeerRecord {
+            agent_string: agent.to_string(),
+            fork_digest: Some("e7a75d5a".to_string()),
+            finalized_epoch: Some(epoch),
+            finalized_root: Some(root.to_string()),
+            ..Default::default()
+        };
+        let mut peers = vec![
+            peer("Lighthouse/v0.2.0", 10, "0xaa"),
+            peer("Prysm/v1.0.0", 10, "0xaa"),
+            peer("Prysm/v1.0.0", 10, "0xaa"),
+            peer("Teku/v0.12.0", 10, "0xbb"),
+            peer("Teku/v0.12.0", 10, "0xbb"),
+            peer("Nimbus", 9, "0xcc"),
+        ];
+        let groups = checkpoint_groups(&peers);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[1].finalized_root, "0xaa");
+        assert_eq!(groups[1].clients["Prysm"], 2);
+
+        let split = divergences(&groups);
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].finalized_epoch, 10);
+        assert_eq!(split[0].groups[1].clients["Teku"], 2);
+
+        // a single peer on another root is not a split
+        peers.truncate(4);
+        assert!(divergences(&checkpoint_groups(&peers)).is_empty());
+    }
+
+    #[test]
+    fn test_block_tree() {
+        let root = Hash256::repeat_byte;
+        let mut tree = BlockTree::default();
+        assert!(tree.insert(root(1), Hash256::zero(), Slot::new(1), 7, 0).is_empty());
+        assert!(tree.insert(root(2), root(1), Slot::new(2), 8, 0).is_empty());
+
+        let reorg = tree.insert(root(3), root(1), Slot::new(3), 9, 0);
+        assert_eq!(reorg.len(), 1);
+        assert_eq!(reorg[0].event, "reorg");
+        assert_eq!(reorg[0].depth, Some(1));
+        assert_eq!(reorg[0].old_head_slot, Some(2));
+        assert_eq!(reorg[0].common_ancestor_slot, Some(1));
+
+        // once the fork is settled the abandoned block is reported
+        let settled = tree.insert(root(4), root(3), Slot::new(68), 10, 0);
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].event, "orphaned");
+        assert_eq!(settled[0].slot, 2);
+        assert_eq!(settled[0].proposer_index, Some(8));
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.head(), Some((root(4), Slot::new(68))));
+    }
+
+    #[test]
+    fn test_slot_stats() {
+        let root = Hash256::repeat_byte;
+        let mut stats = SlotStats::new(4000);
+        assert_eq!(stats.on_block(30, root(1), 5, Some(1000)), (vec![], vec![]));
+
+        let (slots, epochs) = stats.on_block(32, root(2), 6, Some(5000));
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].outcome, "proposed");
+        assert_eq!(slots[0].timeliness, Some("on_time"));
+        assert!(epochs.is_empty());
+
+        // slot 31 never got a block, and settling it completes epoch 0
+        let (slots, epochs) = stats.on_block(33, root(3), 5, Some(-10));
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].slot, 31);
+        assert_eq!(slots[0].outcome, "missed");
+        assert_eq!(epochs.len(), 1);
+        assert_eq!((epochs[0].slots, epochs[0].proposed, epochs[0].missed), (2, 1, 1));
+        assert_eq!(stats.epochs(10).len(), 1);
+
+        let proposers = stats.proposers(10);
+        assert_eq!((proposers[0].proposer_index, proposers[0].blocks), (5, 2));
+        assert_eq!((proposers[1].proposer_index, proposers[1].late_blocks), (6, 1));
+
+        // a long gap restarts the series instead of reporting every slot
+        assert_eq!(stats.on_block(5000, root(4), 7, None), (vec![], vec![]));
+        // a bogus slot does not stop the series
+        assert_eq!(stats.on_block(u64::max_value(), root(5), 8, None), (vec![], vec![]));
+        assert_eq!(stats.on_block(5001, root(6), 9, None), (vec![], vec![]));
+        let (slots, _) = stats.on_block(5003, root(7), 10, None);
+        assert_eq!(slots.iter().map(|x| x.slot).collect::<Vec<_>>(), vec![5001, 5002]);
+    }
+
+    #[test]
+    fn test_participation() {
+        let root = Hash256::repeat_byte;
+        let mut participation = Participation::default();
+        // two aggregates of one committee overlap in a single vote
+        participation.on_attestation(0, 0, 0, root(1), &[true, false, true, false]);
+        participation.on_attestation(0, 0, 0, root(1), &[true, true, false, false]);
+        participation.on_attestation(0, 1, 0, root(2), &[false, false, false, true]);
+        participation.on_attestation(1, 0, 0, root(1), &[true, false]);
+        // the target of a vote must be the epoch of its slot
+        participation.on_attestation(2, 0, 1, root(1), &[true, true]);
+        // and its committee one the spec has
+        participation.on_attestation(2, 64, 0, root(1), &[true, true]);
+
+        let epochs = participation.epochs(10);
+        assert_eq!(epochs.len(), 1);
+        assert!(!epochs[0].complete);
+        assert_eq!((epochs[0].committees, epochs[0].validators, epochs[0].attesters), (3, 10, 5));
+        assert_eq!(epochs[0].slots.len(), 2);
+        assert_eq!(epochs[0].slots[0].rate, 0.5);
+        assert_eq!(epochs[0].targets[0].target_root, format!("{:?}", root(1)));
+        assert_eq!(epochs[0].targets[0].attesters, 4);
+        assert_eq!(epochs[0].targets[1].attesters, 1);
+
+        // votes two epochs later complete epoch 0, and late votes for it are ignored
+        participation.on_attestation(64, 0, 2, root(3), &[true]);
+        participation.on_attestation(2, 0, 0, root(1), &[true, true]);
+        let epochs = participation.epochs(10);
+        assert_eq!(epochs.len(), 2);
+        assert_eq!(epochs[0].epoch, 2);
+        assert!(epochs[1].complete);
+        assert_eq!(epochs[1].attesters, 5);
+    }
+
+    #[test]
+    fn test_rotation() {
+        assert_eq!(
+            rotated_file_name(Path::new("/tmp/.imp/gossip.csv"), "20200801120000"),
+            "gossip-20200801120000.csv"
+        );
+        assert_eq!(
+            rotated_file_name(Path::new("crawler9000"), "20200801120000"),
+            "crawler9000-20200801120000"
+        );
+
+        let rollover = Rollover {
+            max_bytes: Some(1024),
+            max_age: Some(Duration::from_secs(60)),
+        };
+        assert!(!rollover.is_due(1023, Duration::from_secs(59)));
+        assert!(rollover.is_due(1024, Duration::from_secs(0)));
+        assert!(rollover.is_due(0, Duration::from_secs(60)));
+        assert!(!Rollover::default().is_due(u64::max_value(), Duration::from_secs(86400)));
+
+        let parse = |args: &[&str]| {
+            let arg_matches = clap::App::new("imp")
+                .args_from_usage(
+                    "--rotate-size [MiB]
+                    --rotate-interval [SECONDS]
+                    --retention-days [DAYS]
+                    --retention-size [GiB]",
+                )
+                .get_matches_from(std::iter::once(&"imp").chain(args));
+            Rotation::from_arg_matches(&arg_matches)
+        };
+        let rotation = parse(&["--rotate-size", "2", "--retention-days", "1"]).unwrap();
+        assert_eq!(rotation.rollover.max_bytes, Some(2 * 1024 * 1024));
+        assert_eq!(rotation.retention.max_age, Some(Duration::from_secs(86400)));
+        assert!(parse(&["--rotate-size", "0"]).is_err());
+        assert!(parse(&["--rotate-interval", "0"]).is_err());
+        assert!(parse(&["--retention-size", "18446744073709551615"]).is_err());
+    }
+
+    #[test]
+    fn test_parquet_flush() {
+        let dir = test_dir("parquet-flush");
+        let mut rotation = Rotation::default();
+        rotation.rollover.max_age = Some(Duration::from_millis(50));
+        let mut sink = ParquetSink::create(&dir, "gossip", rotation).unwrap();
+        sink.write(GossipRecord::default()).unwrap();
+        // a partial row group reaches the file on the flush tick
+        sink.flush().unwrap();
+        assert!(std::fs::metadata(dir.join("gossip.parquet")).unwrap().len() > 0);
+
+        // and the flush tick rolls the file over once it is old enough
+        std::thread::sleep(Duration::from_millis(60));
+        sink.write(GossipRecord::default()).unwrap();
+        sink.flush().unwrap();
+        let archived: Vec<_> = std::fs::read_dir(dir.join(ARCHIVE_DIR))
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .collect();
+        assert_eq!(archived.len(), 1);
+        let reader = SerializedFileReader::new(std::fs::File::open(&archived[0]).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+    }
+
+    fn archive_twice(compression: Compression) -> Vec<Vec<u8>> {
+        let dir = test_dir(&format!("archive-{:?}", compression));
+        let rotation = Rotation {
+            compression,
+            ..Rotation::default()
+        };
+        let path = dir.join("gossip.csv");
+        let mut archived = vec![];
+        for content in &["first", "second"] {
+            std::fs::write(&path, content).unwrap();
+            archived.push(rotation.archive(&path).unwrap().unwrap());
+            assert!(!path.exists());
+        }
+        // rotations within the same second must not overwrite each other
+        assert_ne!(archived[0], archived[1]);
+        assert_eq!(std::fs::read_dir(dir.join(ARCHIVE_DIR)).unwrap().count(), 2);
+        archived.iter().map(|x| std::fs::read(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_archive() {
+        let dir = test_dir("archive-empty");
+        let path = dir.join("gossip.csv");
+        assert_eq!(Rotation::default().archive(&path).unwrap(), None);
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(Rotation::default().archive(&path).unwrap(), None);
+
+        assert_eq!(archive_twice(Compression::None), vec![b"first".to_vec(), b"second".to_vec()]);
+
+        let gzip = archive_twice(Compression::Gzip);
+        let mut decoded = String::new();
+        GzDecoder::new(&gzip[1][..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "second");
+
+        let zst = archive_twice(Compression::Zstd);
+        assert_eq!(zstd::stream::decode_all(&zst[0][..]).unwrap(), b"first".to_vec());
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = test_dir("prune");
+        for name in &["a.csv", "b.csv", "c.csv"] {
+            std::fs::write(dir.join(name), "0123456789").unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let remaining = || {
+            let mut names: Vec<_> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|x| x.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let mut rotation = Rotation::default();
+        rotation.retention.max_bytes = Some(30);
+        rotation.retention.max_age = Some(Duration::from_secs(3600));
+        rotation.prune(&dir).unwrap();
+        assert_eq!(remaining(), vec!["a.csv", "b.csv", "c.csv"]);
+
+        // oldest files go first until the archive fits
+        rotation.retention.max_bytes = Some(15);
+        rotation.prune(&dir).unwrap();
+        assert_eq!(remaining(), vec!["c.csv"]);
+
+        rotation.retention.max_bytes = None;
+        rotation.retention.max_age = Some(Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(20));
+        rotation.prune(&dir).unwrap();
+        assert!(remaining().is_empty());
+    }
+
+    #[test]
+    fn test_crawler_cli() {
+        let parse = |interval: &str| {
+            crawler::cli_app().get_matches_from_safe(vec!["crawler", "--query-interval", interval])
+        };
+        assert!(parse("5").is_ok());
+        assert!(parse("0").is_err());
+        assert!(parse("soon").is_err());
+    }
+
+    /// 16 nodes, one k-bucket, so every node fits into the crawler's routing
+    /// table whatever its distance and a crawl can always find all of them.
+    fn test_network_specs() -> Vec<NodeSpec> {
+        let mut specs = vec![];
+        for i in 0..10 {
+            specs.push(NodeSpec::new(MEDALLA, vec![i % 64, (i + 32) % 64]));
+        }
+        for _ in 0..4 {
+            specs.push(NodeSpec::new(ALTONA, vec![]));
+        }
+        specs.push(NodeSpec::without_fork());
+        specs.push(NodeSpec::without_fork());
+        specs
+    }
+
+    #[tokio::test]
+    async fn test_crawler_coverage() {
+        let network = TestNetwork::start(&test_network_specs());
+        let total = network.nodes.len();
+        let datadir = test_dir("crawler-coverage");
+        let boot_enr = network.boot_enr();
+        let port = free_port().to_string();
+        let arg_matches = crawler_args(&[
+            "imp",
+            "--output-format",
+            "jsonl",
+            "crawler",
+            "--datadir",
+            datadir.to_str().unwrap(),
+            "--port",
+            &port,
+            "--query-interval",
+            "1",
+            "--max-nodes",
+            &total.to_string(),
+            "--duration",
+            "120",
+            "--boot-nodes",
+            &boot_enr,
+        ]);
+        let crawler = Crawler::new(&arg_matches, vec![], test_logger()).unwrap();
+        let state = crawler.state();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(Events::None);
+        let summary = crawler.find_nodes(shutdown_rx, test_logger()).await;
+
+        assert_eq!(
+            summary.stop_reason,
+            StopReason::MaxNodes,
+            "found {} of {}",
+            summary.stats.nodes,
+            total
+        );
+        assert_eq!(summary.stats.nodes, total);
+        assert_eq!(summary.exit_code(), 0);
+
+        // nodes are grouped by the fork digest of their ENR
+        for stat in &summary.forks {
+            let expected = match stat.network.as_str() {
+                "medalla" => network.count(Some(MEDALLA)),
+                "altona" => network.count(Some(ALTONA)),
+                "none" => network.count(None),
+                other => panic!("unexpected network {}", other),
+            };
+            assert_eq!(stat.nodes, expected);
+        }
+        let medalla_subnets = state.read().unwrap().subnets(Some("e7a75d5a"));
+        assert!(medalla_subnets.keys().all(|x| *x < 64));
+        assert!(state.read().unwrap().subnets(Some("fdca39b0")).is_empty());
+
+        // jsonl is a snapshot format, the final snapshot has one line per node
+        let output = datadir.join(format!("crawler{}.jsonl", port));
+        assert_eq!(line_count(&output), total);
+        assert!(datadir.join(format!("crawler{}-summary.json", port)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_crawler_shutdown() {
+        let network = TestNetwork::start(&test_network_specs());
+        let datadir = test_dir("crawler-shutdown");
+        let boot_enr = network.boot_enr();
+        let port = free_port().to_string();
+        let arg_matches = crawler_args(&[
+            "imp",
+            "crawler",
+            "--datadir",
+            datadir.to_str().unwrap(),
+            "--port",
+            &port,
+            "--query-interval",
+            "1",
+            "--boot-nodes",
+            &boot_enr,
+        ]);
+        let crawler = Crawler::new(&arg_matches, vec![], test_logger()).unwrap();
+        let (shutdown_tx, shutdown_rx) = watch::channel(Events::None);
+        let crawl = tokio::spawn(crawler.find_nodes(shutdown_rx, test_logger()));
+        tokio::time::delay_for(Duration::from_secs(5)).await;
+        shutdown_tx.broadcast(Events::ShutdownMessage).unwrap();
+        let summary = tokio::time::timeout(Duration::from_secs(10), crawl)
+            .await
+            .expect("crawler did not stop after shutdown")
+            .unwrap();
+
+        assert_eq!(summary.stop_reason, StopReason::Shutdown);
+        assert!(summary.stats.nodes > 0);
+        // the final snapshot is still written on shutdown
+        let snapshot = datadir.join(format!("crawler{}.csv", port));
+        assert!(line_count(&snapshot) >= summary.stats.nodes);
+    }
 }
@@ -0,0 +1,190 @@
+use crate::metrics;
+use slog::warn;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Records queued before producers have to wait for the writer.
+const QUEUE_CAPACITY: usize = 65536;
+/// Most records written between two checks of the flush interval.
+const BATCH_SIZE: usize = 1024;
+/// Time between flushes while records keep arriving.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Destination of a writer thread.
+pub trait RecordSink<R>: Send + 'static {
+    fn write(&mut self, record: R) -> io::Result<()>;
+    /// Called every `FLUSH_INTERVAL` while records keep arriving.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Called once when the writer stops.
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+enum Message<R> {
+    Record(R),
+    Close,
+}
+
+/// Sends records to a dedicated writer thread, keeping file io off the
+/// network callbacks.
+pub struct RecordWriter<R> {
+    name: &'static str,
+    sender: SyncSender<Message<R>>,
+    queued: Arc<AtomicUsize>,
+}
+
+/// Owner of the writer thread. Closing it writes and flushes every record
+/// queued so far.
+pub struct WriterHandle<R> {
+    sender: SyncSender<Message<R>>,
+    thread: JoinHandle<()>,
+}
+
+/// Starts a writer thread for `sink`. `name` labels the writer metrics.
+pub fn spawn<R, S>(name: &'static str, sink: S, log: slog::Logger) -> (RecordWriter<R>, WriterHandle<R>)
+where
+    R: Send + 'static,
+    S: RecordSink<R>,
+{
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+    let queued = Arc::new(AtomicUsize::new(0));
+    let thread_queued = queued.clone();
+    let thread = thread::Builder::new()
+        .name(format!("{}-writer", name))
+        .spawn(move || run(name, sink, receiver, thread_queued, log))
+        .expect("failed to spawn writer thread");
+    (
+        RecordWriter {
+            name,
+            sender: sender.clone(),
+            queued,
+        },
+        WriterHandle { sender, thread },
+    )
+}
+
+// derived Clone would require R: Clone
+impl<R> Clone for RecordWriter<R> {
+    fn clone(&self) -> Self {
+        RecordWriter {
+            name: self.name,
+            sender: self.sender.clone(),
+            queued: self.queued.clone(),
+        }
+    }
+}
+
+impl<R> RecordWriter<R> {
+    /// Queues `record`. If the queue is full this waits for the writer and
+    /// counts the stall, so records are never dropped silently.
+    pub fn write(&self, record: R) {
+        // counted before sending so the writer never sees a negative queue
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        let sent = match self.sender.try_send(Message::Record(record)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(message)) => {
+                let stalled_at = Instant::now();
+                let sent = self.sender.send(message).is_ok();
+                metrics::WRITER_STALL_SECONDS
+                    .with_label_values(&[self.name])
+                    .observe(stalled_at.elapsed().as_secs_f64());
+                sent
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        };
+        if sent {
+            metrics::WRITER_QUEUE_LENGTH
+                .with_label_values(&[self.name])
+                .set(queued as i64);
+        } else {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            metrics::WRITER_DROPPED_RECORDS
+                .with_label_values(&[self.name])
+                .inc();
+        }
+    }
+}
+
+impl<R> WriterHandle<R> {
+    /// Writes the remaining records, flushes the sink and stops the thread.
+    pub fn close(self) {
+        if self.sender.send(Message::Close).is_ok() {
+            let _ = self.thread.join();
+        }
+    }
+}
+
+fn run<R, S: RecordSink<R>>(
+    name: &'static str,
+    mut sink: S,
+    receiver: mpsc::Receiver<Message<R>>,
+    queued: Arc<AtomicUsize>,
+    log: slog::Logger,
+) {
+    let mut flushed_at = Instant::now();
+    let mut pending = 0;
+    loop {
+        let timeout = FLUSH_INTERVAL
+            .checked_sub(flushed_at.elapsed())
+            .unwrap_or_default();
+        let closed = match receiver.recv_timeout(timeout) {
+            Ok(Message::Record(record)) => {
+                let mut closed = false;
+                let mut batch = 1;
+                write(name, &mut sink, record, &log);
+                // drain what is already queued without waiting
+                while batch < BATCH_SIZE {
+                    match receiver.try_recv() {
+                        Ok(Message::Record(record)) => {
+                            write(name, &mut sink, record, &log);
+                            batch += 1;
+                        }
+                        Ok(Message::Close) => {
+                            closed = true;
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                pending += batch;
+                let queued = queued.fetch_sub(batch, Ordering::Relaxed) - batch;
+                metrics::WRITER_QUEUE_LENGTH
+                    .with_label_values(&[name])
+                    .set(queued as i64);
+                metrics::WRITER_BATCH_SIZE
+                    .with_label_values(&[name])
+                    .observe(batch as f64);
+                closed
+            }
+            Ok(Message::Close) | Err(RecvTimeoutError::Disconnected) => true,
+            Err(RecvTimeoutError::Timeout) => false,
+        };
+        if closed {
+            if let Err(e) = sink.close() {
+                warn!(log, "Failed to close {} output: {}", name, e);
+            }
+            break;
+        }
+        if pending > 0 && flushed_at.elapsed() >= FLUSH_INTERVAL {
+            if let Err(e) = sink.flush() {
+                warn!(log, "Failed to flush {} records: {}", name, e);
+            }
+            pending = 0;
+            flushed_at = Instant::now();
+        } else if pending == 0 {
+            flushed_at = Instant::now();
+        }
+    }
+}
+
+fn write<R, S: RecordSink<R>>(name: &str, sink: &mut S, record: R, log: &slog::Logger) {
+    if let Err(e) = sink.write(record) {
+        warn!(log, "Failed to write {} record: {}", name, e);
+    }
+}
@@ -1,21 +1,34 @@
 use clap::ArgMatches;
 use crawler::Crawler;
-use p2p::{crawler, P2PAdapter};
+use p2p::api::{self, ApiState};
+use p2p::feed::Feed;
+use p2p::peers::PeerSnapshots;
+use p2p::replay::Replay;
+use p2p::{crawler, metrics, ConnectionWatch, P2PAdapter};
 use slog::{debug, info, o, trace, warn};
 use std::any::type_name;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use tokio::sync::watch;
+use std::sync::Arc;
+use tokio::sync::{oneshot, watch};
 use tokio::{runtime, signal, task, time};
 use types::events::Events;
 
 pub struct Service {
     run_mode: String,
+    metrics_address: Option<SocketAddr>,
+    api_address: Option<SocketAddr>,
+    api_state: Arc<ApiState>,
     p2p_adapter: Option<P2PAdapter>,
+    peer_snapshots: Option<PeerSnapshots>,
+    connection_watch: Option<ConnectionWatch>,
     crawler: Option<Crawler>,
+    replay: Option<Replay>,
     log: slog::Logger,
 }
 
 impl Service {
+    /// Fails if the configuration of the selected mode is invalid.
     pub fn new(
         runtime: &runtime::Runtime,
         client_name: String,
@@ -25,13 +38,26 @@ impl Service {
         mut enrs: Vec<String>,
         arg_matches: &ArgMatches<'_>,
         log: slog::Logger,
-    ) -> Self {
+    ) -> Result<Self, String> {
         let mut run_mode = "node";
         if let Some(matches) = arg_matches.subcommand_matches("crawler") {
             run_mode = "crawler";
         }
+        if arg_matches.subcommand_matches("replay").is_some() {
+            run_mode = "replay";
+        }
+
+        let metrics_address = server_address(arg_matches, "metrics");
+        let api_address = server_address(arg_matches, "http");
+        // the live feed is served by the api
+        let feed = api_address.map(|_| Feed::new());
+
+        let replay = match run_mode {
+            "replay" => Some(Replay::new(arg_matches, testnet_dir.clone())?),
+            _ => None,
+        };
 
-        let (p2p_adapter, crawler) = match run_mode {
+        let (mut p2p_adapter, crawler) = match run_mode {
             "node" => (
                 Some(P2PAdapter::new(
                     &runtime,
@@ -41,48 +67,116 @@ impl Service {
                     testnet_dir,
                     enrs,
                     &arg_matches,
+                    feed.clone(),
                     log.new(o!("NetworkService" => "P2PAdapter")),
-                )),
+                )?),
                 None,
             ),
             "crawler" => {
-                let crawler =
-                    Crawler::new(arg_matches, enrs, log.new(o!("Network Service" => "Crawler")));
+                let mut crawler =
+                    Crawler::new(arg_matches, enrs, log.new(o!("Network Service" => "Crawler")))?;
+                if let Some(feed) = &feed {
+                    crawler = crawler.with_feed(feed.clone());
+                }
                 (None, Some(crawler))
             }
             _ => (None, None),
         };
 
-        Service {
+        let api_state = Arc::new(ApiState {
+            crawler: crawler.as_ref().map(|crawler| crawler.state()),
+            feed,
+            propagation: p2p_adapter.as_ref().map(|adapter| adapter.propagation()),
+            peers: p2p_adapter.as_ref().map(|adapter| adapter.peers()),
+            slot_stats: p2p_adapter.as_ref().map(|adapter| adapter.slot_stats()),
+            participation: p2p_adapter.as_ref().map(|adapter| adapter.participation()),
+        });
+        let peer_snapshots = p2p_adapter
+            .as_mut()
+            .and_then(|adapter| adapter.take_peer_snapshots());
+        let connection_watch = p2p_adapter
+            .as_mut()
+            .and_then(|adapter| adapter.take_connection_watch());
+
+        Ok(Service {
             run_mode: run_mode.into(),
+            metrics_address,
+            api_address,
+            api_state,
             p2p_adapter,
+            peer_snapshots,
+            connection_watch,
             crawler,
+            replay,
             log,
-        }
+        })
     }
-    pub async fn spawn(self, mut shutdown_rx: watch::Receiver<Events>) {
+    /// Spawns the network tasks. The returned receiver resolves with an exit
+    /// code if the service stops on its own, e.g. when a bounded crawl ends.
+    /// The returned task finishes after shutdown, once the outputs are
+    /// flushed and closed.
+    pub async fn spawn(
+        self,
+        mut shutdown_rx: watch::Receiver<Events>,
+    ) -> (oneshot::Receiver<i32>, task::JoinHandle<()>) {
+        let (exit_tx, exit_rx) = oneshot::channel();
         let run_mode = self.run_mode;
         let p2p_adapter = self.p2p_adapter;
         let crawler = self.crawler;
+        let replay = self.replay;
         let crawler_log = self.log.clone();
         let service_log = self.log.clone();
         let crawler_shutdown_rx = shutdown_rx.clone();
-        task::spawn(async move {
-            if let "crawler" = run_mode.as_str() {
-                task::spawn(async move {
-                    let _ = match crawler {
-                        Some(crawler) => {
-                            crawler
-                                .find_nodes(
-                                    crawler_shutdown_rx,
-                                    crawler_log.new(o!("Network Service" => "Crawler")),
-                                )
-                                .await
-                        }
-                        None => (),
-                    };
-                });
-            }
+        if let Some(metrics_address) = self.metrics_address {
+            task::spawn(metrics::serve(
+                metrics_address,
+                shutdown_rx.clone(),
+                self.log.new(o!("Network Service" => "Metrics")),
+            ));
+        }
+        if let Some(peer_snapshots) = self.peer_snapshots {
+            task::spawn(peer_snapshots.run(
+                shutdown_rx.clone(),
+                self.log.new(o!("Network Service" => "Peers")),
+            ));
+        }
+        if let Some(connection_watch) = self.connection_watch {
+            task::spawn(connection_watch.run(
+                shutdown_rx.clone(),
+                self.log.new(o!("Network Service" => "Connections")),
+            ));
+        }
+        if let Some(api_address) = self.api_address {
+            task::spawn(api::serve(
+                api_address,
+                self.api_state.clone(),
+                shutdown_rx.clone(),
+                self.log.new(o!("Network Service" => "API")),
+            ));
+        }
+        let service_task = task::spawn(async move {
+            let mode_task = if let "crawler" = run_mode.as_str() {
+                Some(task::spawn(async move {
+                    if let Some(crawler) = crawler {
+                        let summary = crawler
+                            .find_nodes(
+                                crawler_shutdown_rx,
+                                crawler_log.new(o!("Network Service" => "Crawler")),
+                            )
+                            .await;
+                        let _ = exit_tx.send(summary.exit_code());
+                    }
+                }))
+            } else if let Some(replay) = replay {
+                let replay_shutdown_rx = crawler_shutdown_rx.clone();
+                let replay_log = crawler_log.new(o!("Network Service" => "Replay"));
+                Some(task::spawn(async move {
+                    let exit_code = replay.run(replay_shutdown_rx, replay_log).await;
+                    let _ = exit_tx.send(exit_code);
+                }))
+            } else {
+                None
+            };
 
             loop {
                 if let Some(Events::ShutdownMessage) = shutdown_rx.recv().await {
@@ -95,15 +189,34 @@ impl Service {
                 }
             }
 
-            match run_mode.as_str() {
-                "node" => {
-                    let _ = match p2p_adapter {
-                        Some(p2p_adapter) => p2p_adapter.close(),
-                        None => Err(()),
-                    };
-                }
-                _ => (),
-            };
+            if let Some(p2p_adapter) = p2p_adapter {
+                // joining the writer threads must not block the runtime
+                let _ = task::spawn_blocking(move || p2p_adapter.close()).await;
+            }
+            // the crawler and replay write their final output on shutdown
+            if let Some(mode_task) = mode_task {
+                let _ = mode_task.await;
+            }
         });
+        (exit_rx, service_task)
+    }
+}
+
+/// Returns the socket address of an optional http server enabled with `--<name>`
+/// and configured with `--<name>-address` and `--<name>-port`.
+fn server_address(arg_matches: &ArgMatches<'_>, name: &str) -> Option<SocketAddr> {
+    if !arg_matches.is_present(name) {
+        return None;
     }
+    let address = arg_matches
+        .value_of(format!("{}-address", name))
+        .expect("required parameter")
+        .parse::<IpAddr>()
+        .unwrap_or_else(|_| panic!("Invalid {} address", name));
+    let port = arg_matches
+        .value_of(format!("{}-port", name))
+        .expect("required parameter")
+        .parse::<u16>()
+        .unwrap_or_else(|_| panic!("Invalid {} port", name));
+    Some(SocketAddr::new(address, port))
 }
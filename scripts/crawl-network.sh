@@ -74,38 +74,30 @@ else
     exit 1
 fi
 
-TIMESTAMP=$(date +%s)
 DATA_DIR=$HOME/.$NETWORK
-BACKUP_DIR=$HOME/.imp/$NETWORK/$TIMESTAMP
 if [ $HOME = "/" ]; then
     if [[ -z "${PWD//*\/scripts*/}" ]]; then
         DATA_DIR=$PWD/../.$NETWORK
-        BACKUP_DIR=$PWD/../.imp/$NETWORK/$TIMESTAMP
     else
         DATA_DIR=$PWD/.$NETWORK
-        BACKUP_DIR=$PWD/.imp/$NETWORK/$TIMESTAMP
     fi
 fi
 mkdir -p $DATA_DIR
-mkdir -p $BACKUP_DIR
 
 
 BOOTNODES=
 BOOTNODES=$BOOTSTRAP_BOOTNODES
 
-if [ "$OUTPUT_MODE" != "none" ]; then
-    echo "Backing up $DATA_DIR to $BACKUP_DIR"
-    cp -r $DATA_DIR $BACKUP_DIR/
-    rm -f $DATA_DIR/crawler*
-fi
+# imp archives the previous crawl output under $DATA_DIR/archive on startup
+ROTATION_OPTS="--rotate-compression gzip --retention-days 30"
 PORT=12000
 for i in $(seq 1 $NUM_CRAWLERS); do
     echo cat $DATA_DIR/crawler$PORT.csv
     if [ -f $DATA_DIR/enrs.csv ]; then 
         echo "Additional bootnodes found in file"
-        RUST_LOG=libp2p_discv5=debug ./../target/debug/imp --p2p-protocol-version imp/libp2p --enr-file $DATA_DIR/enrs.csv --debug-level trace crawler --output-mode $OUTPUT_MODE --datadir $DATA_DIR --listen-address $IP_ADDRESS --port $PORT --fork-digest "$FORK_DIGEST" --boot-nodes $BOOTNODES &
+        RUST_LOG=libp2p_discv5=debug ./../target/debug/imp --p2p-protocol-version imp/libp2p $ROTATION_OPTS --enr-file $DATA_DIR/enrs.csv --debug-level trace crawler --output-mode $OUTPUT_MODE --datadir $DATA_DIR --listen-address $IP_ADDRESS --port $PORT --fork-digest "$FORK_DIGEST" --boot-nodes $BOOTNODES &
     else
-        RUST_LOG=libp2p_discv5=debug ./../target/debug/imp --p2p-protocol-version imp/libp2p --debug-level trace crawler --output-mode $OUTPUT_MODE --datadir $DATA_DIR --listen-address $IP_ADDRESS --port $PORT --fork-digest "$FORK_DIGEST" --boot-nodes $BOOTNODES &
+        RUST_LOG=libp2p_discv5=debug ./../target/debug/imp --p2p-protocol-version imp/libp2p $ROTATION_OPTS --debug-level trace crawler --output-mode $OUTPUT_MODE --datadir $DATA_DIR --listen-address $IP_ADDRESS --port $PORT --fork-digest "$FORK_DIGEST" --boot-nodes $BOOTNODES &
     fi
     
     let PORT++;
@@ -2,6 +2,7 @@ extern crate error_chain;
 extern crate target_info;
 use agent::Agent;
 use clap::{App, Arg};
+use futures::future;
 use network::NetworkService;
 use p2p;
 use slog::{debug, info, o, trace, warn};
@@ -43,6 +44,119 @@ fn main() -> Result<(), std::io::Error> {
                 .help("The /path/to/enr.txt")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("output-format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .help("Format of the data written by the crawler and agent.")
+                .takes_value(true)
+                .possible_values(p2p::output::OUTPUT_FORMATS)
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::with_name("payload-archive")
+                .long("payload-archive")
+                .value_name("DIR")
+                .help("Store every unique raw gossip payload and an index of its arrivals in DIR.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("attnets")
+                .long("attnets")
+                .value_name("SUBNETS")
+                .help("Attestation subnets the agent subscribes to and advertises: all, none or a comma separated list.")
+                .takes_value(true)
+                .default_value("none"),
+        )
+        .arg(
+            Arg::with_name("peer-snapshot-interval")
+                .long("peer-snapshot-interval")
+                .value_name("SECONDS")
+                .help("Time between snapshots of the agent's peer table.")
+                .takes_value(true)
+                .default_value("60"),
+        )
+        .arg(
+            Arg::with_name("rotate-size")
+                .long("rotate-size")
+                .value_name("MiB")
+                .help("Rotate output files once they reach this size.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rotate-interval")
+                .long("rotate-interval")
+                .value_name("SECONDS")
+                .help("Rotate output files after this many seconds.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rotate-compression")
+                .long("rotate-compression")
+                .value_name("TYPE")
+                .help("Compression applied to rotated output files.")
+                .takes_value(true)
+                .possible_values(p2p::rotation::COMPRESSION_TYPES)
+                .default_value("none"),
+        )
+        .arg(
+            Arg::with_name("retention-days")
+                .long("retention-days")
+                .value_name("DAYS")
+                .help("Delete rotated output files older than this many days.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retention-size")
+                .long("retention-size")
+                .value_name("GiB")
+                .help("Delete the oldest rotated output files once they exceed this size.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("http")
+                .long("http")
+                .help("Serve a read-only JSON api over http.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("http-address")
+                .long("http-address")
+                .value_name("ADDRESS")
+                .help("The address the api server will listen on.")
+                .takes_value(true)
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            Arg::with_name("http-port")
+                .long("http-port")
+                .value_name("PORT")
+                .help("The port the api server will listen on.")
+                .takes_value(true)
+                .default_value("5052"),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .long("metrics")
+                .help("Serve prometheus metrics over http.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("metrics-address")
+                .long("metrics-address")
+                .value_name("ADDRESS")
+                .help("The address the metrics server will listen on.")
+                .takes_value(true)
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            Arg::with_name("metrics-port")
+                .long("metrics-port")
+                .value_name("PORT")
+                .help("The port the metrics server will listen on.")
+                .takes_value(true)
+                .default_value("5054"),
+        )
         .arg(
             Arg::with_name("debug-level")
                 .long("debug-level")
@@ -54,6 +168,7 @@ fn main() -> Result<(), std::io::Error> {
         )
         .subcommand(p2p::cli_app())
         .subcommand(p2p::crawler::cli_app())
+        .subcommand(p2p::replay::cli_app())
         .get_matches();
 
     let p2p_protocol_version = arg_matches.value_of("p2p-protocol-version").unwrap();
@@ -89,7 +204,7 @@ fn main() -> Result<(), std::io::Error> {
 
     info!(log, "Starting imp");
 
-    let network_service = NetworkService::new(
+    let network_service = match NetworkService::new(
         &runtime,
         client_name,
         platform,
@@ -98,24 +213,45 @@ fn main() -> Result<(), std::io::Error> {
         enrs,
         &arg_matches,
         log.new(o!("imp" => "NetworkService")),
-    );
+    ) {
+        Ok(network_service) => network_service,
+        Err(e) => {
+            warn!(log, "Failed to start imp: {}", e);
+            // the logger is asynchronous
+            sleep(Duration::new(1, 0));
+            std::process::exit(1);
+        }
+    };
     let agent = Agent::new(log.new(o!("imp" => "Agent")));
 
     let (shutdown_tx, shutdown_rx) = watch::channel::<Events>(Events::None);
 
     // main "event loop"
-    runtime.block_on(async move {
-        async move {
-            network_service.spawn(shutdown_rx.clone()).await;
-            agent.spawn(shutdown_rx).await;
-        }
-        .await;
-        // block the current thread until SIGINT is received.
-        signal::ctrl_c().await.expect("failed to listen for event");
+    let (exit_code, service_task) = runtime.block_on(async move {
+        let (exit_rx, service_task) = network_service.spawn(shutdown_rx.clone()).await;
+        agent.spawn(shutdown_rx).await;
+        // a service that never stops on its own drops its sender
+        let finished = async move {
+            match exit_rx.await {
+                Ok(exit_code) => exit_code,
+                Err(_) => future::pending().await,
+            }
+        };
+        // block the current thread until SIGINT is received or the service is done.
+        let exit_code = tokio::select! {
+            result = signal::ctrl_c() => {
+                result.expect("failed to listen for event");
+                None
+            },
+            exit_code = finished => Some(exit_code),
+        };
+        (exit_code, service_task)
     });
 
     warn!(log, "Sending shutdown signal.");
     let _ = shutdown_tx.broadcast(Events::ShutdownMessage);
+    // wait for the outputs to be flushed before the runtime goes away
+    let _ = runtime.block_on(service_task);
 
     sleep(Duration::new(1, 0));
 
@@ -123,5 +259,8 @@ fn main() -> Result<(), std::io::Error> {
     let _ = runtime.shutdown_timeout(tokio::time::Duration::from_millis(300));
 
     warn!(log.clone(), "Exiting imp.");
+    if let Some(exit_code) = exit_code {
+        std::process::exit(exit_code);
+    }
     Ok(())
 }